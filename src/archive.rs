@@ -0,0 +1,142 @@
+// Reads single files out of .tar, .tar.gz and .zip archives, so they can be used as sources
+// without unpacking them. Also reads and writes whole tar archives, for bundles
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::process::exit;
+
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+
+pub fn is_archive(path: &Path) -> bool {
+    let name = path.to_string_lossy();
+    [".tar", ".tar.gz", ".tgz", ".zip"]
+        .iter()
+        .any(|x| name.ends_with(x))
+}
+
+// Contents of file inside archive, None if there is no such file in it
+pub fn read(archive: &Path, file: &str) -> Option<Vec<u8>> {
+    let result = if archive.to_string_lossy().ends_with(".zip") {
+        read_zip(archive, file)
+    } else {
+        read_tar(archive, file)
+    };
+
+    return result.unwrap_or_else(|err| {
+        eprintln!(
+            "ERROR: Couldn't read '{file}' from '{archive}': {err}",
+            archive = archive.display()
+        );
+        exit(1);
+    });
+}
+
+// Entries are often stored as './file', those match file too
+fn same_path(entry: &Path, file: &str) -> bool {
+    let entry: PathBuf = entry
+        .components()
+        .filter(|x| *x != Component::CurDir)
+        .collect();
+    return entry == Path::new(file);
+}
+
+fn read_tar(archive: &Path, file: &str) -> io::Result<Option<Vec<u8>>> {
+    let reader: Box<dyn Read> = if archive.to_string_lossy().ends_with(".tar") {
+        Box::new(File::open(archive)?)
+    } else {
+        Box::new(GzDecoder::new(File::open(archive)?))
+    };
+
+    let mut tar = tar::Archive::new(reader);
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        if entry.header().entry_type().is_file() && same_path(&entry.path()?, file) {
+            let mut contents: Vec<u8> = vec![];
+            entry.read_to_end(&mut contents)?;
+            return Ok(Some(contents));
+        }
+    }
+
+    return Ok(None);
+}
+
+fn read_zip(archive: &Path, file: &str) -> io::Result<Option<Vec<u8>>> {
+    let mut zip = zip::ZipArchive::new(File::open(archive)?)?;
+    for i in 0..zip.len() {
+        let mut entry = zip.by_index(i)?;
+        let matches = entry.enclosed_name().is_some_and(|x| same_path(&x, file));
+        if entry.is_file() && matches {
+            let mut contents: Vec<u8> = vec![];
+            entry.read_to_end(&mut contents)?;
+            return Ok(Some(contents));
+        }
+    }
+
+    return Ok(None);
+}
+
+// Writes a tar archive holding files, gzipped if path ends in .gz or .tgz
+pub fn write_tar(path: &Path, files: &[(String, Vec<u8>)]) {
+    let result = File::create(path).and_then(|file| {
+        let name = path.to_string_lossy();
+        let writer: Box<dyn Write> = if name.ends_with(".gz") || name.ends_with(".tgz") {
+            Box::new(GzEncoder::new(file, Compression::default()))
+        } else {
+            Box::new(file)
+        };
+
+        let mut tar = tar::Builder::new(writer);
+        for (name, contents) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            tar.append_data(&mut header, name, contents.as_slice())?;
+        }
+        tar.into_inner()?.flush()
+    });
+
+    if let Err(err) = result {
+        eprintln!(
+            "ERROR: Couldn't write to '{file}': {err}",
+            file = path.display()
+        );
+        exit(1);
+    }
+}
+
+// Every file in a tar archive, with its path
+pub fn read_tar_files(path: &Path) -> Vec<(PathBuf, Vec<u8>)> {
+    let result = (|| -> io::Result<Vec<(PathBuf, Vec<u8>)>> {
+        let name = path.to_string_lossy();
+        let reader: Box<dyn Read> = if name.ends_with(".gz") || name.ends_with(".tgz") {
+            Box::new(GzDecoder::new(File::open(path)?))
+        } else {
+            Box::new(File::open(path)?)
+        };
+
+        let mut files: Vec<(PathBuf, Vec<u8>)> = vec![];
+        let mut tar = tar::Archive::new(reader);
+        for entry in tar.entries()? {
+            let mut entry = entry?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+            let name = entry.path()?.to_path_buf();
+            let mut contents: Vec<u8> = vec![];
+            entry.read_to_end(&mut contents)?;
+            files.push((name, contents));
+        }
+        Ok(files)
+    })();
+
+    return result.unwrap_or_else(|err| {
+        eprintln!(
+            "ERROR: Couldn't read '{file}': {err}",
+            file = path.display()
+        );
+        exit(1);
+    });
+}
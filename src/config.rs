@@ -0,0 +1,71 @@
+// Defaults for options, read from $XDG_CONFIG_HOME/fswap/config (~/.config/fswap/config by
+// default). One KEY = VALUE per line, '#' starts a comment:
+//
+//   trash = true            move removed files to the trash, as if --trash was given
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::exit;
+
+pub struct Config {
+    pub trash: bool,
+}
+
+fn config_path() -> Option<PathBuf> {
+    let config = match env::var_os("XDG_CONFIG_HOME").filter(|x| !x.is_empty()) {
+        Some(x) => PathBuf::from(x),
+        None => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    return Some(config.join("fswap").join("config"));
+}
+
+pub fn load() -> Config {
+    let mut config = Config { trash: false };
+
+    let path = match config_path() {
+        Some(x) if x.exists() => x,
+        _ => return config,
+    };
+
+    let contents = fs::read_to_string(&path).unwrap_or_else(|err| {
+        eprintln!(
+            "ERROR: Couldn't read '{file}': {err}",
+            file = path.display()
+        );
+        exit(1);
+    });
+
+    for (n, line) in contents.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let invalid = || -> ! {
+            eprintln!(
+                "ERROR: Invalid line {n} in '{file}': {line}",
+                n = n + 1,
+                file = path.display()
+            );
+            exit(1);
+        };
+
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => invalid(),
+        };
+        let flag = match value {
+            "true" | "yes" | "1" => true,
+            "false" | "no" | "0" => false,
+            _ => invalid(),
+        };
+
+        match key {
+            "trash" => config.trash = flag,
+            _ => invalid(),
+        }
+    }
+
+    return config;
+}
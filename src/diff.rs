@@ -0,0 +1,741 @@
+// Line based diffing and merging. Lines keep their '\n', so joining them gives back the file
+
+use std::path::Path;
+
+// A region where a and b differ: a[a_start..a_start + a_len] was replaced by
+// b[b_start..b_start + b_len]
+#[derive(Clone, Copy)]
+pub struct Hunk {
+    pub a_start: usize,
+    pub a_len: usize,
+    pub b_start: usize,
+    pub b_len: usize,
+}
+
+pub fn lines(text: &str) -> Vec<&str> {
+    text.split_inclusive('\n').collect()
+}
+
+// Myers' diff, returns the differing regions in order. Uses the linear space variant, keeping
+// every round of the search around takes O(D^2) memory on files that differ a lot
+pub fn diff(a: &[&str], b: &[&str]) -> Vec<Hunk> {
+    let mut a_kept = vec![false; a.len()];
+    let mut b_kept = vec![false; b.len()];
+    mark_kept(a, b, (0, a.len()), (0, b.len()), &mut a_kept, &mut b_kept);
+
+    let mut hunks: Vec<Hunk> = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a_kept[i] && b_kept[j] {
+            i += 1;
+            j += 1;
+            continue;
+        }
+
+        let (a_start, b_start) = (i, j);
+        while i < a.len() && !a_kept[i] {
+            i += 1;
+        }
+        while j < b.len() && !b_kept[j] {
+            j += 1;
+        }
+        hunks.push(Hunk {
+            a_start,
+            a_len: i - a_start,
+            b_start,
+            b_len: j - b_start,
+        });
+    }
+
+    return hunks;
+}
+
+// Marks the lines of a[a_range] and b[b_range] that are on a shortest edit path. Splits the
+// ranges at the middle snake of the path and recurses on both sides of it
+fn mark_kept(
+    a: &[&str],
+    b: &[&str],
+    (mut a_lo, mut a_hi): (usize, usize),
+    (mut b_lo, mut b_hi): (usize, usize),
+    a_kept: &mut [bool],
+    b_kept: &mut [bool],
+) {
+    // Common lines at either end are always kept
+    while a_lo < a_hi && b_lo < b_hi && a[a_lo] == b[b_lo] {
+        a_kept[a_lo] = true;
+        b_kept[b_lo] = true;
+        a_lo += 1;
+        b_lo += 1;
+    }
+    while a_lo < a_hi && b_lo < b_hi && a[a_hi - 1] == b[b_hi - 1] {
+        a_hi -= 1;
+        b_hi -= 1;
+        a_kept[a_hi] = true;
+        b_kept[b_hi] = true;
+    }
+    if a_lo == a_hi || b_lo == b_hi {
+        return;
+    }
+
+    let snake = middle_snake(a, b, (a_lo, a_hi), (b_lo, b_hi));
+    for n in 0..snake.len {
+        a_kept[snake.x + n] = true;
+        b_kept[snake.y + n] = true;
+    }
+    mark_kept(
+        a,
+        b,
+        (a_lo, snake.start.0),
+        (b_lo, snake.start.1),
+        a_kept,
+        b_kept,
+    );
+    mark_kept(
+        a,
+        b,
+        (snake.end.0, a_hi),
+        (snake.end.1, b_hi),
+        a_kept,
+        b_kept,
+    );
+}
+
+// The middle of a shortest edit path, from start to end: at most one insertion or deletion, and
+// len matching lines from (x, y)
+struct Snake {
+    start: (usize, usize),
+    end: (usize, usize),
+    x: usize,
+    y: usize,
+    len: usize,
+}
+
+// Searches forwards from the top left and backwards from the bottom right at the same time, until
+// the two searches overlap. vf[k] is the furthest x reached forwards on diagonal k = x - y, vb[c]
+// the furthest back y on diagonal c = k - delta
+fn middle_snake(a: &[&str], b: &[&str], a_range: (usize, usize), b_range: (usize, usize)) -> Snake {
+    let (left, right) = (a_range.0 as isize, a_range.1 as isize);
+    let (top, bottom) = (b_range.0 as isize, b_range.1 as isize);
+    let delta = (right - left) - (bottom - top);
+    let max = ((right - left) + (bottom - top) + 1) / 2;
+    let offset = max + 1;
+    let at = |k: isize| (k + offset) as usize;
+
+    let mut vf = vec![0isize; 2 * max as usize + 3];
+    let mut vb = vec![0isize; 2 * max as usize + 3];
+    vf[at(1)] = left;
+    vb[at(1)] = bottom;
+
+    let snake =
+        |start: (isize, isize), end: (isize, isize), x: isize, y: isize, len: isize| Snake {
+            start: (start.0 as usize, start.1 as usize),
+            end: (end.0 as usize, end.1 as usize),
+            x: x as usize,
+            y: y as usize,
+            len: len as usize,
+        };
+
+    for d in 0..=max {
+        let mut k = d;
+        while k >= -d {
+            let c = k - delta;
+            let (px, mut x) = if k == -d || (k != d && vf[at(k - 1)] < vf[at(k + 1)]) {
+                (vf[at(k + 1)], vf[at(k + 1)])
+            } else {
+                (vf[at(k - 1)], vf[at(k - 1)] + 1)
+            };
+            let mut y = top + (x - left) - k;
+            let py = if d == 0 || x != px { y } else { y - 1 };
+            let (x0, y0) = (x, y);
+            while x < right && y < bottom && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            vf[at(k)] = x;
+            if delta % 2 != 0 && -d < c && c < d && y >= vb[at(c)] {
+                return snake((px, py), (x, y), x0, y0, x - x0);
+            }
+            k -= 2;
+        }
+
+        let mut c = d;
+        while c >= -d {
+            let k = c + delta;
+            let (py, mut y) = if c == -d || (c != d && vb[at(c - 1)] > vb[at(c + 1)]) {
+                (vb[at(c + 1)], vb[at(c + 1)])
+            } else {
+                (vb[at(c - 1)], vb[at(c - 1)] - 1)
+            };
+            let mut x = left + (y - top) + k;
+            let px = if d == 0 || y != py { x } else { x + 1 };
+            let x0 = x;
+            while x > left && y > top && a[x as usize - 1] == b[y as usize - 1] {
+                x -= 1;
+                y -= 1;
+            }
+            vb[at(c)] = y;
+            if delta % 2 == 0 && -d <= k && k <= d && x <= vf[at(k)] {
+                return snake((x, y), (px, py), x, y, x0 - x);
+            }
+            c -= 2;
+        }
+    }
+
+    unreachable!("the searches meet after at most max rounds");
+}
+
+// Three way merge of the changes base -> ours and base -> theirs. Returns the merged text, and
+// how many conflicts were marked in it
+pub fn merge3(
+    base: &str,
+    ours: &str,
+    theirs: &str,
+    ours_name: &str,
+    theirs_name: &str,
+) -> (String, usize) {
+    let base = lines(base);
+    let ours = lines(ours);
+    let theirs = lines(theirs);
+
+    let ours_hunks = diff(&base, &ours);
+    let theirs_hunks = diff(&base, &theirs);
+
+    let mut out = String::new();
+    let mut conflicts = 0;
+    let mut pos = 0; // position in base
+    let (mut oi, mut ti) = (0, 0);
+
+    loop {
+        let next_ours = ours_hunks.get(oi);
+        let next_theirs = theirs_hunks.get(ti);
+        let start = match (next_ours, next_theirs) {
+            (Some(o), Some(t)) => o.a_start.min(t.a_start),
+            (Some(o), None) => o.a_start,
+            (None, Some(t)) => t.a_start,
+            (None, None) => break,
+        };
+
+        base[pos..start].iter().for_each(|x| out.push_str(x));
+
+        // Grow the region until no hunk from either side overlaps its end
+        let mut end = start;
+        let (o_first, t_first) = (oi, ti);
+        loop {
+            let mut grew = false;
+            while let Some(o) = ours_hunks.get(oi)
+                && overlaps(o, start, end)
+            {
+                end = end.max(o.a_start + o.a_len);
+                oi += 1;
+                grew = true;
+            }
+            while let Some(t) = theirs_hunks.get(ti)
+                && overlaps(t, start, end)
+            {
+                end = end.max(t.a_start + t.a_len);
+                ti += 1;
+                grew = true;
+            }
+            if !grew {
+                break;
+            }
+        }
+
+        let ours_side = side(&base, &ours, &ours_hunks[o_first..oi], start, end);
+        let theirs_side = side(&base, &theirs, &theirs_hunks[t_first..ti], start, end);
+
+        if o_first == oi {
+            out.push_str(&theirs_side);
+        } else if t_first == ti || ours_side == theirs_side {
+            out.push_str(&ours_side);
+        } else {
+            conflicts += 1;
+            out.push_str(&format!("<<<<<<< {ours_name}\n"));
+            push_terminated(&mut out, &ours_side);
+            out.push_str("=======\n");
+            push_terminated(&mut out, &theirs_side);
+            out.push_str(&format!(">>>>>>> {theirs_name}\n"));
+        }
+
+        pos = end;
+    }
+
+    base[pos..].iter().for_each(|x| out.push_str(x));
+    return (out, conflicts);
+}
+
+// Whether hunk touches base[start..end]. Insertions right at the edges count as touching, so two
+// insertions at the same spot end up in the same region
+fn overlaps(hunk: &Hunk, start: usize, end: usize) -> bool {
+    hunk.a_start <= end && hunk.a_start + hunk.a_len >= start
+}
+
+// What one side turned base[start..end] into, given its hunks inside that region
+fn side(base: &[&str], other: &[&str], hunks: &[Hunk], start: usize, end: usize) -> String {
+    let mut out = String::new();
+    let mut pos = start;
+    for hunk in hunks {
+        base[pos..hunk.a_start].iter().for_each(|x| out.push_str(x));
+        other[hunk.b_start..hunk.b_start + hunk.b_len]
+            .iter()
+            .for_each(|x| out.push_str(x));
+        pos = hunk.a_start + hunk.a_len;
+    }
+    base[pos..end].iter().for_each(|x| out.push_str(x));
+    return out;
+}
+
+// Conflict markers have to start on their own line
+fn push_terminated(out: &mut String, text: &str) {
+    out.push_str(text);
+    if !text.is_empty() && !text.ends_with('\n') {
+        out.push('\n');
+    }
+}
+
+// Prints hunk in unified diff format, with up to context unchanged lines around it
+pub fn format_hunk(a: &[&str], b: &[&str], hunk: &Hunk, context: usize) -> String {
+    format_hunks(a, b, std::slice::from_ref(hunk), context)
+}
+
+// Same for hunks close enough to share their context, as a single hunk
+fn format_hunks(a: &[&str], b: &[&str], hunks: &[Hunk], context: usize) -> String {
+    let (first, last) = (hunks[0], hunks[hunks.len() - 1]);
+    let before = context.min(first.a_start).min(first.b_start);
+    let after = context
+        .min(a.len() - last.a_start - last.a_len)
+        .min(b.len() - last.b_start - last.b_len);
+
+    let a_start = first.a_start - before;
+    let b_start = first.b_start - before;
+    let a_len = last.a_start + last.a_len + after - a_start;
+    let b_len = last.b_start + last.b_len + after - b_start;
+
+    let mut out = format!(
+        "@@ -{a} +{b} @@\n",
+        a = range(a_start, a_len),
+        b = range(b_start, b_len)
+    );
+    let mut push = |prefix: char, lines: &[&str]| {
+        for line in lines {
+            out.push(prefix);
+            out.push_str(line);
+            if !line.ends_with('\n') {
+                out.push_str("\n\\ No newline at end of file\n");
+            }
+        }
+    };
+
+    let mut pos = a_start;
+    for hunk in hunks {
+        push(' ', &a[pos..hunk.a_start]);
+        push('-', &a[hunk.a_start..hunk.a_start + hunk.a_len]);
+        push('+', &b[hunk.b_start..hunk.b_start + hunk.b_len]);
+        pos = hunk.a_start + hunk.a_len;
+    }
+    push(' ', &a[pos..pos + after]);
+
+    return out;
+}
+
+// Every difference between a and b in unified diff format, without the file headers
+pub fn unified(a: &str, b: &str, context: usize) -> String {
+    let a = lines(a);
+    let b = lines(b);
+    let hunks = diff(&a, &b);
+
+    let mut out = String::new();
+    let mut start = 0;
+    for i in 0..hunks.len() {
+        let end = hunks[i].a_start + hunks[i].a_len;
+        let joined = hunks
+            .get(i + 1)
+            .is_some_and(|x| x.a_start - end <= 2 * context);
+        if !joined {
+            out.push_str(&format_hunks(&a, &b, &hunks[start..=i], context));
+            start = i + 1;
+        }
+    }
+    return out;
+}
+
+// Line ranges in hunk headers are 1 based, and empty ranges point at the line before them
+fn range(start: usize, len: usize) -> String {
+    match len {
+        0 => format!("{start},0"),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{len}", start + 1),
+    }
+}
+
+// The changes a unified diff makes to one file. Paths are None for /dev/null, so files the patch
+// creates have no old_path and files it deletes no new_path
+pub struct FilePatch {
+    pub old_path: Option<String>,
+    pub new_path: Option<String>,
+    pub hunks: Vec<PatchHunk>,
+}
+
+// old holds the context and removed lines, new the context and added lines, both with their '\n'
+pub struct PatchHunk {
+    pub old_start: usize,
+    pub old: Vec<String>,
+    pub new: Vec<String>,
+}
+
+pub fn is_patch(path: &Path) -> bool {
+    let name = path.to_string_lossy();
+    name.ends_with(".diff") || name.ends_with(".patch")
+}
+
+// Reads the files of a unified diff, as made by diff -u or git diff. Anything outside of the
+// hunks is ignored. Returns the line number of the first malformed hunk on failure
+pub fn parse_patch(text: &str) -> Result<Vec<FilePatch>, usize> {
+    let mut patches: Vec<FilePatch> = vec![];
+    let lines = lines(text);
+    let mut i = 0;
+
+    while i < lines.len() {
+        let line = lines[i].trim_end_matches('\n');
+        if let Some(old) = line.strip_prefix("--- ")
+            && let Some(new) = lines.get(i + 1).and_then(|x| x.strip_prefix("+++ "))
+        {
+            patches.push(FilePatch {
+                old_path: patch_path(old, "a/"),
+                new_path: patch_path(new.trim_end_matches('\n'), "b/"),
+                hunks: vec![],
+            });
+            i += 2;
+            continue;
+        }
+
+        let header = match line.strip_prefix("@@ -") {
+            Some(x) => x,
+            None => {
+                i += 1;
+                continue;
+            }
+        };
+        let patch = patches.last_mut().ok_or(i + 1)?;
+        let (old_start, old_len, new_len) = hunk_header(header).ok_or(i + 1)?;
+
+        let mut hunk = PatchHunk {
+            old_start,
+            old: vec![],
+            new: vec![],
+        };
+        i += 1;
+
+        // Which sides the last line went to, for '\ No newline at end of file'
+        let mut last = (false, false);
+        while i < lines.len()
+            && (hunk.old.len() < old_len || hunk.new.len() < new_len || lines[i].starts_with('\\'))
+        {
+            // Some tools drop the space of empty context lines
+            let line = lines[i];
+            let (prefix, text) = match line.chars().next() {
+                Some('\n') => (' ', line),
+                Some(c) => (c, &line[c.len_utf8()..]),
+                None => return Err(i + 1),
+            };
+
+            last = match prefix {
+                ' ' => (true, true),
+                '-' => (true, false),
+                '+' => (false, true),
+                '\\' => {
+                    if last.0 {
+                        strip_newline(&mut hunk.old);
+                    }
+                    if last.1 {
+                        strip_newline(&mut hunk.new);
+                    }
+                    i += 1;
+                    continue;
+                }
+                _ => return Err(i + 1),
+            };
+            if last.0 {
+                hunk.old.push(text.to_string());
+            }
+            if last.1 {
+                hunk.new.push(text.to_string());
+            }
+            i += 1;
+        }
+
+        if hunk.old.len() != old_len || hunk.new.len() != new_len {
+            return Err(i);
+        }
+        patch.hunks.push(hunk);
+    }
+
+    return Ok(patches);
+}
+
+// Strips the timestamp some tools add after a tab, and git's a/ or b/ prefix
+fn patch_path(path: &str, prefix: &str) -> Option<String> {
+    let path = path.split('\t').next().unwrap_or(path);
+    if path == "/dev/null" {
+        return None;
+    }
+    return Some(path.strip_prefix(prefix).unwrap_or(path).to_string());
+}
+
+// Reads 'l,s +l,s @@', returns the old start (0 based) and the lengths of both sides
+fn hunk_header(header: &str) -> Option<(usize, usize, usize)> {
+    let (ranges, _) = header.split_once(" @@")?;
+    let (old, new) = ranges.split_once(" +")?;
+    let range = |x: &str| -> Option<(usize, usize)> {
+        match x.split_once(',') {
+            Some((start, len)) => Some((start.parse().ok()?, len.parse().ok()?)),
+            None => Some((x.parse().ok()?, 1)),
+        }
+    };
+
+    let (old_start, old_len) = range(old)?;
+    let (_, new_len) = range(new)?;
+    // Empty ranges point at the line before them
+    let old_start = if old_len == 0 {
+        old_start
+    } else {
+        old_start.saturating_sub(1)
+    };
+    return Some((old_start, old_len, new_len));
+}
+
+fn strip_newline(lines: &mut [String]) {
+    if let Some(line) = lines.last_mut()
+        && line.ends_with('\n')
+    {
+        line.pop();
+    }
+}
+
+// Applies hunks to text. Hunks are looked for where they say they start first, then anywhere
+// after the previous hunk. Returns the index of the first hunk that didn't fit on failure
+pub fn apply_patch(text: &str, hunks: &[PatchHunk]) -> Result<String, usize> {
+    let lines = lines(text);
+    let mut out = String::new();
+    let mut pos = 0;
+
+    for (n, hunk) in hunks.iter().enumerate() {
+        let fits = |at: usize| {
+            at + hunk.old.len() <= lines.len()
+                && hunk.old.iter().zip(&lines[at..]).all(|(a, b)| a == b)
+        };
+
+        let at = if hunk.old_start >= pos && fits(hunk.old_start) {
+            hunk.old_start
+        } else {
+            (pos..=lines.len()).find(|x| fits(*x)).ok_or(n)?
+        };
+
+        lines[pos..at].iter().for_each(|x| out.push_str(x));
+        hunk.new.iter().for_each(|x| out.push_str(x));
+        pos = at + hunk.old.len();
+    }
+
+    lines[pos..].iter().for_each(|x| out.push_str(x));
+    return Ok(out);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diff_finds_the_changed_lines() {
+        let a = lines("a\nb\nc\nd\n");
+        let b = lines("a\nB\nc\nd\ne\n");
+        let hunks: Vec<(usize, usize, usize, usize)> = diff(&a, &b)
+            .iter()
+            .map(|x| (x.a_start, x.a_len, x.b_start, x.b_len))
+            .collect();
+        assert_eq!(hunks, [(1, 1, 1, 1), (4, 0, 4, 1)]);
+    }
+
+    #[test]
+    fn diff_is_shortest() {
+        // Small pseudo random files, checked against the length of their longest common
+        // subsequence and rebuilt from the hunks
+        let mut seed: u64 = 1;
+        let mut next = |n: u64| {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (seed >> 33) % n
+        };
+        for _ in 0..300 {
+            let a: Vec<String> = (0..next(12)).map(|_| next(4).to_string()).collect();
+            let b: Vec<String> = (0..next(12)).map(|_| next(4).to_string()).collect();
+            let a: Vec<&str> = a.iter().map(|x| x.as_str()).collect();
+            let b: Vec<&str> = b.iter().map(|x| x.as_str()).collect();
+
+            let mut lcs = vec![vec![0; b.len() + 1]; a.len() + 1];
+            for i in (0..a.len()).rev() {
+                for j in (0..b.len()).rev() {
+                    lcs[i][j] = if a[i] == b[j] {
+                        lcs[i + 1][j + 1] + 1
+                    } else {
+                        lcs[i + 1][j].max(lcs[i][j + 1])
+                    };
+                }
+            }
+
+            let hunks = diff(&a, &b);
+            let edits: usize = hunks.iter().map(|x| x.a_len + x.b_len).sum();
+            assert_eq!(edits, a.len() + b.len() - 2 * lcs[0][0], "{a:?} {b:?}");
+
+            let mut rebuilt: Vec<&str> = vec![];
+            let mut i = 0;
+            for hunk in &hunks {
+                rebuilt.extend(&a[i..hunk.a_start]);
+                rebuilt.extend(&b[hunk.b_start..hunk.b_start + hunk.b_len]);
+                i = hunk.a_start + hunk.a_len;
+            }
+            rebuilt.extend(&a[i..]);
+            assert_eq!(rebuilt, b, "{a:?} {b:?}");
+        }
+    }
+
+    #[test]
+    fn unified_pure_insertion() {
+        assert_eq!(unified("a\nb\n", "a\nx\nb\n", 0), "@@ -1,0 +2 @@\n+x\n");
+        assert_eq!(unified("", "x\n", 3), "@@ -0,0 +1 @@\n+x\n");
+    }
+
+    #[test]
+    fn unified_missing_final_newline() {
+        assert_eq!(
+            unified("a\n", "a\nb", 3),
+            "@@ -1 +1,2 @@\n a\n+b\n\\ No newline at end of file\n"
+        );
+        assert_eq!(
+            unified("a\nb", "a\nb\n", 3),
+            "@@ -1,2 +1,2 @@\n a\n-b\n\\ No newline at end of file\n+b\n"
+        );
+    }
+
+    #[test]
+    fn unified_joins_close_hunks() {
+        let a = "1\n2\n3\n4\n5\n";
+        let b = "x\n2\n3\n4\ny\n";
+        assert_eq!(
+            unified(a, b, 2),
+            "@@ -1,5 +1,5 @@\n-1\n+x\n 2\n 3\n 4\n-5\n+y\n"
+        );
+        assert_eq!(
+            unified(a, b, 1),
+            "@@ -1,2 +1,2 @@\n-1\n+x\n 2\n@@ -4,2 +4,2 @@\n 4\n-5\n+y\n"
+        );
+    }
+
+    // Hunks of a patch with a single file
+    fn patch_hunks(text: &str) -> Vec<PatchHunk> {
+        let mut patches = parse_patch(text).expect("valid patch");
+        assert_eq!(patches.len(), 1);
+        return patches.remove(0).hunks;
+    }
+
+    #[test]
+    fn parse_patch_paths() {
+        let patches = parse_patch(
+            "--- a/f\t2024-01-01\n+++ b/f\n@@ -1 +1 @@\n-a\n+b\n--- /dev/null\n+++ b/g\n@@ -0,0 +1 @@\n+c\n",
+        )
+        .expect("valid patch");
+        let paths: Vec<(Option<String>, Option<String>)> = patches
+            .into_iter()
+            .map(|x| (x.old_path, x.new_path))
+            .collect();
+        assert_eq!(
+            paths,
+            [
+                (Some(String::from("f")), Some(String::from("f"))),
+                (None, Some(String::from("g")))
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_patch_malformed_hunk() {
+        assert!(matches!(
+            parse_patch("--- a/f\n+++ b/f\n@@ -1,2 +1,2 @@\n a\n*b\n"),
+            Err(5)
+        ));
+        assert!(matches!(parse_patch("@@ -1 +1 @@\n-a\n+b\n"), Err(1)));
+    }
+
+    #[test]
+    fn apply_pure_insertion() {
+        let hunks = patch_hunks("--- a/f\n+++ b/f\n@@ -1,0 +2 @@\n+x\n");
+        assert_eq!(hunks[0].old_start, 1);
+        assert!(matches!(
+            apply_patch("a\nb\n", &hunks).as_deref(),
+            Ok("a\nx\nb\n")
+        ));
+
+        let hunks = patch_hunks("--- /dev/null\n+++ b/f\n@@ -0,0 +1,2 @@\n+x\n+y\n");
+        assert!(matches!(apply_patch("", &hunks).as_deref(), Ok("x\ny\n")));
+    }
+
+    #[test]
+    fn apply_missing_final_newline() {
+        for (a, b) in [("a\n", "a\nb"), ("a\nb", "a\nb\n"), ("a", "b")] {
+            let patch = format!("--- a/f\n+++ b/f\n{}", unified(a, b, 3));
+            let hunks = patch_hunks(&patch);
+            assert!(matches!(apply_patch(a, &hunks).as_deref(), Ok(x) if x == b));
+        }
+    }
+
+    #[test]
+    fn apply_moved_hunk() {
+        // Two lines were added at the top since the patch was made
+        let hunks = patch_hunks("--- a/f\n+++ b/f\n@@ -1,3 +1,3 @@\n a\n-b\n+B\n c\n");
+        assert!(matches!(
+            apply_patch("x\ny\na\nb\nc\n", &hunks).as_deref(),
+            Ok("x\ny\na\nB\nc\n")
+        ));
+
+        // Only looked for after the previous hunk
+        let hunks = patch_hunks("--- a/f\n+++ b/f\n@@ -3 +3 @@\n-c\n+C\n@@ -1 +1 @@\n-a\n+A\n");
+        assert!(matches!(apply_patch("a\nb\nc\n", &hunks), Err(1)));
+    }
+
+    #[test]
+    fn apply_missing_hunk() {
+        let hunks = patch_hunks("--- a/f\n+++ b/f\n@@ -1 +1 @@\n-z\n+Z\n");
+        assert!(matches!(apply_patch("a\nb\n", &hunks), Err(0)));
+    }
+
+    #[test]
+    fn merge_conflicting_edits_on_the_same_line() {
+        let (merged, conflicts) = merge3("a\nb\nc\n", "a\nX\nc\n", "a\nY\nc\n", "ours", "theirs");
+        assert_eq!(
+            merged,
+            "a\n<<<<<<< ours\nX\n=======\nY\n>>>>>>> theirs\nc\n"
+        );
+        assert_eq!(conflicts, 1);
+    }
+
+    #[test]
+    fn merge_same_edit_on_both_sides() {
+        let (merged, conflicts) = merge3("a\nb\nc\n", "a\nX\nc\n", "a\nX\nc\n", "ours", "theirs");
+        assert_eq!(merged, "a\nX\nc\n");
+        assert_eq!(conflicts, 0);
+    }
+
+    #[test]
+    fn merge_separate_edits() {
+        let (merged, conflicts) = merge3("a\nb\nc\n", "A\nb\nc\n", "a\nb\nC\n", "ours", "theirs");
+        assert_eq!(merged, "A\nb\nC\n");
+        assert_eq!(conflicts, 0);
+    }
+
+    #[test]
+    fn merge_conflict_without_final_newline() {
+        let (merged, conflicts) = merge3("a\n", "b", "c", "ours", "theirs");
+        assert_eq!(merged, "<<<<<<< ours\nb\n=======\nc\n>>>>>>> theirs\n");
+        assert_eq!(conflicts, 1);
+    }
+}
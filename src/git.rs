@@ -0,0 +1,54 @@
+// Reads files from git revisions through the git binary
+
+use std::path::Path;
+use std::process::{Command, Stdio, exit};
+
+fn git(dir: &Path) -> Command {
+    let mut command = Command::new("git");
+    command.arg("-C").arg(dir).stderr(Stdio::null());
+    return command;
+}
+
+// Whether rev names a commit in the repository dir is in
+pub fn valid_rev(dir: &Path, rev: &str) -> bool {
+    let status = git(dir)
+        .args(["rev-parse", "--verify", "--quiet"])
+        .arg(format!("{rev}^{{commit}}"))
+        .stdout(Stdio::null())
+        .status()
+        .unwrap_or_else(|err| {
+            eprintln!("ERROR: Couldn't run git: {err}");
+            exit(1);
+        });
+    return status.success();
+}
+
+// Contents of file as of rev, file is relative to dir. None if it isn't a file in rev
+pub fn show(dir: &Path, rev: &str, file: &str) -> Option<Vec<u8>> {
+    let output = git(dir)
+        .args(["cat-file", "blob"])
+        .arg(format!("{rev}:./{file}"))
+        .output()
+        .unwrap_or_else(|err| {
+            eprintln!("ERROR: Couldn't run git: {err}");
+            exit(1);
+        });
+    return output.status.success().then_some(output.stdout);
+}
+
+// The commit id rev currently points to
+pub fn resolve(dir: &Path, rev: &str) -> String {
+    let output = git(dir)
+        .args(["rev-parse", "--verify"])
+        .arg(format!("{rev}^{{commit}}"))
+        .output()
+        .unwrap_or_else(|err| {
+            eprintln!("ERROR: Couldn't run git: {err}");
+            exit(1);
+        });
+    if !output.status.success() {
+        eprintln!("ERROR: '{rev}' isn't a git revision.");
+        exit(1);
+    }
+    return String::from_utf8_lossy(&output.stdout).trim().to_string();
+}
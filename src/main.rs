@@ -1,24 +1,45 @@
+// explicit returns are the house style
+#![allow(clippy::needless_return)]
+
 extern crate pathdiff;
 
+mod archive;
+mod config;
+mod diff;
+mod git;
+mod registry;
+mod session;
+mod trash;
+mod undo;
+mod watch;
+
+use session::{DEFAULT_SOURCE, Session, Source};
 use std::collections::VecDeque;
 use std::env;
 use std::ffi::OsString;
 use std::fs::{self, DirEntry, File};
-use std::io::{self, Read, Write};
+use std::io::{self, Write};
+use std::os::fd::AsRawFd;
 use std::path::{Path, PathBuf};
 use std::process::exit;
-use std::str;
 
-const FSWAP_EXT: &'static str = ".fswap";
+use sha2::{Digest, Sha256};
+
+const FSWAP_EXT: &str = ".fswap";
+const FSWAP_STORE: &str = ".fswap.d";
+
+// The metadata file of a bundle, see bundle_create
+const BUNDLE_META: &str = "fswap-bundle";
+
+// How long sources have to be quiet before watch copies anything, editors tend to write files in
+// several steps
+const WATCH_DEBOUNCE_MS: i32 = 250;
 
 // contains: if None, returns every file
-fn find_files_with(path: &PathBuf, contains: Option<&str>) -> Option<Vec<String>> {
-    let contains = match contains {
-        Some(x) => x,
-        None => &"",
-    };
+fn find_files_with(path: &Path, contains: Option<&str>) -> Option<Vec<String>> {
+    let contains = contains.unwrap_or("");
 
-    let start_files = fs::read_dir(&path).unwrap_or_else(|err| {
+    let start_files = fs::read_dir(path).unwrap_or_else(|err| {
         eprintln!(
             "ERROR: Couldn't read dir '{dir}': {err}",
             dir = path.display()
@@ -53,6 +74,11 @@ fn find_files_with(path: &PathBuf, contains: Option<&str>) -> Option<Vec<String>
         });
 
         if file_md.is_dir() {
+            // Files kept by fswap itself are never part of the fswap directory
+            if file.file_name() == FSWAP_STORE {
+                continue;
+            }
+
             let sub_files = fs::read_dir(file.path()).unwrap_or_else(|err| {
                 eprintln!(
                     "ERROR: Couldn't read dir '{dir}': {err}",
@@ -82,13 +108,44 @@ fn find_files_with(path: &PathBuf, contains: Option<&str>) -> Option<Vec<String>
         }
     }
 
-    if fswap_files.len() > 0 {
+    if !fswap_files.is_empty() {
         Some(fswap_files)
     } else {
         None
     }
 }
 
+// Directories under root with a .fswap file. Unlike find_files_with, dirs that can't be read are
+// skipped with a warning, a tree full of other people's files shouldn't stop the search
+fn find_sessions(root: &Path) -> Vec<PathBuf> {
+    let mut dirs: VecDeque<PathBuf> = VecDeque::from([root.to_path_buf()]);
+    let mut sessions: Vec<PathBuf> = vec![];
+
+    while let Some(dir) = dirs.pop_front() {
+        let entries = match fs::read_dir(&dir) {
+            Ok(x) => x,
+            Err(err) => {
+                eprintln!(
+                    "WARNING: Skipped '{dir}', couldn't read it: {err}",
+                    dir = dir.display()
+                );
+                continue;
+            }
+        };
+
+        for entry in entries.flatten() {
+            let is_dir = entry.file_type().is_ok_and(|x| x.is_dir());
+            if is_dir && entry.file_name() != FSWAP_STORE {
+                dirs.push_back(entry.path());
+            } else if !is_dir && entry.file_name() == FSWAP_EXT {
+                sessions.push(dir.clone());
+            }
+        }
+    }
+
+    return sessions;
+}
+
 fn confirm_cmd(description: &String) -> bool {
     let stdin = io::stdin();
     let mut stdout = io::stdout();
@@ -100,7 +157,7 @@ fn confirm_cmd(description: &String) -> bool {
         });
         let mut buf = String::new();
         match stdin.read_line(&mut buf) {
-            Ok(n) if n == 2 => {
+            Ok(2) => {
                 let buf = buf.trim().to_ascii_lowercase();
                 if buf.eq("y") {
                     return true;
@@ -121,66 +178,244 @@ fn confirm_cmd(description: &String) -> bool {
     }
 }
 
-fn cmd_begin(u_input: &mut UserInput) -> bool {
-    let arg = u_input.next_arg();
-    let source_dir = Path::new(&arg);
+// Exits if path isn't an existing directory
+fn check_dir(path: &Path) {
+    if !path.exists() {
+        eprintln!("ERROR: '{dir}' doesn't exist.", dir = path.display());
+        exit(1);
+    }
 
-    let arg: String;
-    if u_input.argc > 0 {
-        arg = u_input.next_arg();
-    } else {
-        arg = String::from(".");
+    let md = path.metadata().unwrap_or_else(|err| {
+        eprintln!(
+            "ERROR: Couldn't get metadata from '{dir}': {err}",
+            dir = path.display()
+        );
+        exit(1);
+    });
+
+    if !md.file_type().is_dir() {
+        eprintln!("ERROR: '{dir}' isn't a directory.", dir = path.display());
+        exit(1);
     }
+}
 
-    let working_dir = Path::new(&arg);
+// Exits if path isn't an existing directory, archive or patch
+fn check_source(path: &Path) {
+    if (archive::is_archive(path) || diff::is_patch(path)) && path.is_file() {
+        return;
+    }
+    check_dir(path);
+}
 
-    // Check both files exist
-    if !source_dir.exists() {
-        eprintln!("ERROR: '{dir}' doesn't exist.", dir = source_dir.display());
+// Path to source_dir as stored in the .fswap file of working_dir
+fn source_path(source_dir: &Path, working_dir: &Path) -> String {
+    let path_diff = pathdiff::diff_paths(source_dir, working_dir).unwrap_or_else(|| {
+        panic!(
+            "ERROR: pathdiff::diff_paths returned None.\nI could never reach this through testing."
+        );
+    });
+
+    if path_diff == Path::new("") {
+        eprintln!("ERROR: Source directory and fswap directory cannot be the same.");
         exit(1);
-    };
+    }
 
-    if !working_dir.exists() {
-        eprintln!("ERROR: '{dir}' doesn't exist.", dir = working_dir.display());
+    return path_diff.display().to_string();
+}
+
+// Checks the source given as arg, and returns how it's stored in the .fswap file of working_dir:
+// relative to it by default, absolute with --absolute, or as given with --template, in which case
+// it's expanded every time the source is used
+fn link_source(arg: &str, working_dir: &Path, opts: &Opts) -> String {
+    if opts.absolute && opts.template {
+        eprintln!("ERROR: '--absolute' and '--template' can't be used together.");
         exit(1);
     }
 
-    // Check files are directories
-    let source_md = source_dir.metadata().unwrap_or_else(|err| {
+    if opts.template {
+        check_source(&working_dir.join(session::expand(arg)));
+        return arg.to_string();
+    }
+
+    let source_dir = Path::new(arg);
+    check_source(source_dir);
+    if !opts.absolute {
+        return source_path(source_dir, working_dir);
+    }
+
+    let path = fs::canonicalize(source_dir).unwrap_or_else(|err| {
         eprintln!(
-            "ERROR: Couldn't get metadata from '{dir}': {err}",
+            "ERROR: Couldn't get the absolute path of '{dir}': {err}",
             dir = source_dir.display()
         );
         exit(1);
     });
+    return path.display().to_string();
+}
 
-    if !source_md.file_type().is_dir() {
-        eprintln!(
-            "ERROR: '{dir}' isn't a directory.",
-            dir = source_dir.display()
-        );
+// Like confirm_cmd, but with more answers than yes or no. Returns None if nothing was picked
+fn choose_cmd(description: &str, choices: &[char]) -> Option<char> {
+    let keys: Vec<String> = choices.iter().map(|x| x.to_string()).collect();
+    let stdin = io::stdin();
+    let mut stdout = io::stdout();
+    loop {
+        print!("CONFIRM: {description}? [{keys}/N] ", keys = keys.join("/"));
+        stdout.flush().unwrap_or_else(|err| {
+            eprintln!("ERROR: Unhandleable io::stdout() error: {err}");
+            exit(1);
+        });
+        let mut buf = String::new();
+        match stdin.read_line(&mut buf) {
+            Ok(2) => {
+                let c = buf.trim().to_ascii_lowercase().chars().next();
+                match c {
+                    Some('n') => return None,
+                    Some(c) if choices.contains(&c) => return Some(c),
+                    _ => continue,
+                }
+            }
+            Ok(_) => {
+                return None;
+            }
+            Err(err) => {
+                eprintln!("ERROR: Couldn't read line: {err}");
+                exit(1);
+            }
+        };
+    }
+}
+
+fn cmd_accept(u_input: &mut UserInput) -> bool {
+    let mut session = Session::open(Path::new("."));
+
+    // The originals are gone afterwards, so accepting everything has to be asked for
+    if u_input.argc == 0 && !u_input.opts.all {
+        eprintln!("ERROR: No files given, use -a to accept every swapped file.");
         exit(1);
     }
 
-    let working_md = working_dir.metadata().unwrap_or_else(|err| {
-        eprintln!(
-            "ERROR: Couldn't get metadata from '{dir}': {err}",
-            dir = working_dir.display()
-        );
+    let files = picked_files(&session, u_input);
+
+    if files.is_empty() {
+        eprintln!("No swapped files found.");
+        return true;
+    }
+
+    if !u_input.opts.noconfirm {
+        let confirmed = confirm_cmd(&format!(
+            "Keep the swapped version of {n} file(s), and delete their originals",
+            n = files.len()
+        ));
+        if !confirmed {
+            return true;
+        }
+    }
+
+    for file in files {
+        accept_file(&mut session, &file, &u_input.opts);
+    }
+
+    return true;
+}
+
+// Keeps the swapped version of file and forgets everything kept for it
+fn accept_file(session: &mut Session, file: &str, opts: &Opts) {
+    let swap = session.swap(file).unwrap_or_else(|| {
+        eprintln!("ERROR: '{file}' isn't swapped.");
         exit(1);
     });
-    if !working_md.file_type().is_dir() {
+
+    undo::record(session, file);
+    if swap.toggled {
+        flip_toggle(session, file);
+    }
+    drop_layers(session, file, opts);
+
+    let working_file = PathBuf::from(file);
+    let swapped_file = append_to_pathbuf(&working_file, FSWAP_EXT);
+    let before = swapped_file.exists().then(|| hash_file(&swapped_file));
+    let after = hash_file(&working_file);
+
+    if swapped_file.exists()
+        && let Err(err) = remove_file(&swapped_file, opts)
+    {
         eprintln!(
-            "ERROR: '{dir}' isn't a directory.",
-            dir = working_dir.display()
+            "ERROR: couldn't remove '{file}': {err}",
+            file = swapped_file.display()
         );
         exit(1);
     }
 
-    // Create and populate .fswap file
+    let source = session.swap(file).map(|x| x.source.clone());
+    session.remove_swap(file);
+    session.save();
+    session.log("accept", file, before.as_deref(), Some(&after));
+
+    if opts.verbose {
+        println!("INFO: Removed '{file}'.", file = swapped_file.display());
+        println!(
+            "INFO: Accepted '{file}' from source '{name}'.",
+            name = source.unwrap_or_default()
+        );
+    }
+}
+
+fn cmd_begin(u_input: &mut UserInput) -> bool {
+    // With --git the source is a revision of the fswap directory's own repository
+    let arg = match &u_input.opts.git {
+        Some(_) => String::from("."),
+        None => u_input.next_arg(),
+    };
+    let source_dir = arg;
+
+    let arg = if u_input.argc > 0 {
+        u_input.next_arg()
+    } else {
+        String::from(".")
+    };
+
+    let working_dir = Path::new(&arg);
+    check_dir(working_dir);
+
+    let path = match &u_input.opts.git {
+        Some(rev) => {
+            if !git::valid_rev(working_dir, rev) {
+                eprintln!(
+                    "ERROR: '{rev}' isn't a git revision of '{dir}'.",
+                    dir = working_dir.display()
+                );
+                exit(1);
+            }
+            String::from(".")
+        }
+        None => link_source(&source_dir, working_dir, &u_input.opts),
+    };
+
     let mut fswap_path = working_dir.to_path_buf();
     fswap_path.push(FSWAP_EXT);
+    if fswap_path.exists() {
+        eprintln!(
+            "ERROR: '{file}' already exists, a session was already begun there.",
+            file = fswap_path.display()
+        );
+        exit(1);
+    }
+
+    // The snapshot is taken first, so the .fswap file isn't left behind empty if it fails
+    let mut session = Session::new(working_dir);
+    let mut source = Source {
+        name: String::from(DEFAULT_SOURCE),
+        path,
+        rev: u_input.opts.git.clone(),
+        template: u_input.opts.template && u_input.opts.git.is_none(),
+    };
+    if u_input.opts.snapshot {
+        snapshot_source(&session, &mut source, u_input.opts.verbose);
+    }
+    let path = source.path.clone();
+    session.sources.push(source);
 
+    // Create and populate .fswap file
     let mut fswap_file = File::create_new(&fswap_path).unwrap_or_else(|err| {
         eprintln!(
             "ERROR: Couldn't create '{file}': {err}",
@@ -189,18 +424,7 @@ fn cmd_begin(u_input: &mut UserInput) -> bool {
         exit(1);
     });
 
-    let path_diff = pathdiff::diff_paths(source_dir, working_dir).unwrap_or_else(|| {
-        panic!(
-            "ERROR: pathdiff::diff_paths returned None.\nI could never reach this through testing."
-        );
-    });
-
-    if path_diff == PathBuf::from("") {
-        eprintln!("ERROR: Source directory and fswap directory cannot be the same.");
-        exit(1);
-    }
-
-    if let Err(err) = write!(fswap_file, "{src}", src = path_diff.as_path().display()) {
+    if let Err(err) = write!(fswap_file, "{}", session.serialize()) {
         eprintln!(
             "ERROR: couldn't write to '{file}': {err}",
             file = fswap_path.display()
@@ -208,11 +432,12 @@ fn cmd_begin(u_input: &mut UserInput) -> bool {
         exit(1);
     }
 
+    registry::add(working_dir);
+
     if u_input.opts.verbose {
         println!(
             "INFO: Created file '{file}', with path to source '{path}'.",
-            file = fswap_path.display(),
-            path = path_diff.display()
+            file = fswap_path.display()
         );
     }
 
@@ -220,30 +445,52 @@ fn cmd_begin(u_input: &mut UserInput) -> bool {
 }
 
 fn cmd_info(u_input: &mut UserInput) -> bool {
-    let arg: String;
-    if u_input.argc > 0 {
-        arg = u_input.next_arg();
+    let arg = if u_input.argc > 0 {
+        u_input.next_arg()
     } else {
-        arg = String::from(".");
-    }
-
-    let mut working_dir = PathBuf::from(arg);
-
-    working_dir.push(FSWAP_EXT);
-    if let Err(err) = File::open(&working_dir) {
-        eprintln!(
-            "ERROR: Couldn't open '{file}': {err}",
-            file = working_dir.display()
-        );
-        exit(1);
-    }
+        String::from(".")
+    };
 
-    working_dir.pop();
+    let working_dir = PathBuf::from(arg);
+    let session = Session::open(&working_dir);
 
     match find_files_with(&working_dir, Some(FSWAP_EXT)) {
         Some(paths) => {
             println!("fswap files in '{dir}':", dir = working_dir.display());
-            paths.iter().for_each(|x| println!("  {x}"));
+            for path in paths {
+                let file = Path::new(&path)
+                    .strip_prefix(&working_dir)
+                    .map(|x| x.display().to_string())
+                    .unwrap_or(path.clone());
+                let swap = file
+                    .strip_suffix(FSWAP_EXT)
+                    .and_then(|x| session.swap(&session::normalize(x)));
+                match swap {
+                    Some(swap) => {
+                        let mut from = format!("from '{src}'", src = swap.source);
+                        for layer in swap.under.iter().rev() {
+                            from.push_str(&format!(" over '{name}'", name = layer.source));
+                        }
+                        if swap.toggled {
+                            from.push_str(", toggled");
+                        }
+                        // Hashed the way refresh does, archive entries included, without
+                        // changing anything
+                        if let Some(source) = session.source(&swap.source)
+                            && let Some(hash) = &swap.hash
+                        {
+                            let source_file = session.source_file(source, &swap.file);
+                            if !source_file.exists() {
+                                from.push_str(", source file missing");
+                            } else if hash_file(&source_file) != *hash {
+                                from.push_str(", source changed");
+                            }
+                        }
+                        println!("  {path} ({from})");
+                    }
+                    None => println!("  {path}"),
+                }
+            }
         }
         None => {
             println!("No fswap files in '{dir}'.", dir = working_dir.display());
@@ -260,29 +507,79 @@ fn cmd_help(u_input: &mut UserInput) -> bool {
     }
 
     let help = match arg.as_str() {
-        "begin"  => String::from("Usage: fswap begin [SOURCE DIR] [FSWAP DIR]\nCreates .fswap file linking SOURCE DIR and FSWAP DIR."),
-        "end"    => String::from("Usage: fswap end [FSWAP DIR]\nDeletes .fswap file, and ALL swapped files. Does not revert changes before doing so."),
+        "accept" => String::from("Usage: fswap accept { -a | FILES... }\nKeeps the swapped version of FILES, deletes their originals and stops tracking them.\nAccepts every swapped file with -a."),
+        "begin"  => String::from("Usage: fswap begin [--snapshot] [--absolute | --template] { SOURCE DIR | --git REV } [FSWAP DIR]\nCreates .fswap file linking SOURCE DIR and FSWAP DIR.\nSOURCE DIR can also be a .tar, .tar.gz or .zip archive.\nWith --git, files are swapped in from git revision REV of the repository FSWAP DIR is in.\n--snapshot copies SOURCE DIR into the session (or pins REV to its commit), so later changes to it don't matter.\nSOURCE DIR is stored relative to FSWAP DIR, unless --absolute or --template is given."),
+        "bundle" => String::from("Usage: fswap bundle { create OUT [FILES...] | apply BUNDLE }\nCreate packs the swapped versions of FILES, their layers and the hashes of their originals into the tar archive OUT.\nApply swaps the same files in again from BUNDLE, if the originals in the fswap directory match, unless --force is given.\nBundles every swapped file if no FILES are given."),
+        "checkpoint"=> String::from("Usage: fswap checkpoint { save NAME | restore NAME | list | remove NAME }\nSaves which files are swapped from which source, and the swapped versions, under NAME.\nRestoring reverts and swaps files until they match the checkpoint again. Swapped versions that differ from their source are put back as saved."),
+        "end"    => String::from("Usage: fswap end [--keep-swapped | --discard] [--trash] [FSWAP DIR]\nReverts all swapped files, then deletes .fswap file and the session's store.\n--keep-swapped accepts them instead, --discard deletes ALL swapped files without reverting them."),
+        "export" => String::from("Usage: fswap export --patch [-o FILE] [FILES...]\nPrints a unified diff from the saved originals of FILES to their swapped in versions, edits included, or writes it to FILE.\nDeleted files are diffed against /dev/null. Exports every swapped file if no FILES are given."),
         "help"   => String::from("Usage: fswap help [COMMAND]\nPrints a brief description of what COMMAND does."),
-        "info"   => String::from("Usage: fswap info [FSWAP DIR]\nPrints all swapped files."),
-        "revert" => String::from("Usage: fswap revert [FILES...]\nReverts a swapped file to it's original state (e.g. file.fswap -> file)."),
-        "swap"   => String::from("Usage: fswap swap [FILES...]\nIf they both exist, swaps a file from SOURCE DIR to FSWAP DIR, and saves the swapped file."),
-        "none"   => format!(r#"Usage: fswap [COMMAND] [OPTIONS...] [ARGUMENTS...]
+        "info"   => String::from("Usage: fswap info [FSWAP DIR]\nPrints all swapped files, and which ones changed in their source since they were swapped."),
+        "log"    => String::from("Usage: fswap log [--since TIME] [--until TIME] [FILES...]\nPrints the history of changes made to FILES, newest first. Prints every change if no FILES are given.\nTIME is how long ago (30m, 2h, 7d), a date and time (YYYY-MM-DD [HH:MM[:SS]]), or @SECONDS since the epoch."),
+        "push"   => String::from("Usage: fswap push [FILES...]\nCopies swapped files that were edited back to their source.\nAsks before overwriting source files that changed since they were swapped. Pushes every swapped file if no FILES are given."),
+        "redo"   => String::from("Usage: fswap redo\nRedoes the change last undone with 'undo'. Anything undone can't be redone anymore once files are changed again."),
+        "refresh"=> String::from("Usage: fswap refresh [FILES...]\nCopies swapped files from their source again if the source changed since they were swapped.\nAsks before overwriting swapped files that were edited. Refreshes every swapped file if no FILES are given."),
+        "relink" => String::from("Usage: fswap relink [--from NAME] [--verify] [--absolute | --template] NEW SOURCE\nPoints source NAME at NEW SOURCE, e.g. after moving it. Checks that every file swapped in from it exists there,\nand with --verify that they are the same as when they were swapped in. --force relinks anyway.\nGit revision sources can't be relinked."),
+        "revert" => String::from("Usage: fswap revert [--all-layers] [--force | --merge] [--trash] [FILES...]\nReverts a swapped file to it's original state (e.g. file.fswap -> file).\nFiles swapped on top of other sources go back one layer, unless --all-layers is given.\nAsks what to do with edits made since the swap, unless --force is given to discard them,\nor --merge to carry them over to the reverted file with a three way merge."),
+        "sessions"=> String::from("Usage: fswap sessions [ROOT]\nLists the sessions registered by begin, or every session under ROOT, with their source,\nhow many files are swapped and how long ago they were begun. Sessions whose .fswap file is gone are forgotten."),
+        "source" => String::from("Usage: fswap source { add [--snapshot] [--absolute | --template] NAME DIR | list | remove NAME }\nManages the source directories files can be swapped in from. 'begin' adds a source named 'default'.\nDIR can also be a .tar, .tar.gz or .zip archive, or a .diff or .patch file."),
+        "swap"   => String::from("Usage: fswap swap [--from NAME | --from-rev REV | --patch FILE] [--rebackup] [-p] [FILES...]\nIf they both exist, swaps a file from SOURCE DIR to FSWAP DIR, and saves the swapped file.\nSwapping an already swapped file from another source stacks NAME on top, 'revert' goes back one layer.\nThe saved original is never overwritten, unless --rebackup is given to save the current file as the new original.\n-p asks about every hunk of the difference, and only swaps in the picked ones.\n--from-rev swaps files in from git revision REV, adding a source for it if needed.\n--patch applies a unified diff to the originals of the files it changes, and swaps them in like any other source.\nSwaps every file the patch changes if no FILES are given."),
+        "toggle" => String::from("Usage: fswap toggle [FILES...]\nFlips swapped files between their original and swapped version, without copying from SOURCE DIR.\nToggles every swapped file if no FILES are given."),
+        "undo"   => String::from("Usage: fswap undo\nUndoes the last swap, revert, accept, toggle, refresh or push, putting every file it changed, their saved\noriginals and layers back the way they were. Can be repeated to undo older changes, see 'redo'."),
+        "watch"  => String::from("Usage: fswap watch [--swap-new [--from NAME]] [--revert-on-exit]\nWatches every source until interrupted, and copies changed files over the files swapped in from them.\n--swap-new also swaps in files added to the source, --revert-on-exit reverts all swapped files when stopped."),
+        "none"   => String::from(r#"Usage: fswap [COMMAND] [OPTIONS...] [ARGUMENTS...]
 
 NOTE: FSWAP DIR is an optional argument, and defaults to the current working directory.
 
 COMMANDS
+  a[ccept]  { -a | FILES... }           keeps swapped files, and deletes their originals
   b[egin]   [SOURCE DIR] [FSWAP DIR]    creates .fswap file linking SOURCE_DIR and FSWAP DIR
-  e[nd]     [FSWAP DIR]                 deletes .fswap file, and ALL swapped files
+  bu[ndle]  { create | apply }          packs swapped files into a tar archive, or swaps them in from one
+  c[heckpoint] { save | restore | ... } saves and restores which files are swapped
+  e[nd]     [FSWAP DIR]                 reverts all swapped files, and deletes .fswap file
+  ex[port]  --patch [FILES...]          prints a patch from the originals to the swapped files
   i[nfo]    [FSWAP DIR]                 prints all swapped files
+  l[og]     [FILES...]                  prints the history of changes made to files
+  p[ush]    [FILES...]                  copies edited swapped files back to their source
+  red[o]                                redoes the last undone change
+  re[fresh] [FILES...]                  copies swapped files whose source changed again
+  rel[ink]  NEW SOURCE                  points a source at a new location
   r[evert]  [FILES...]                  reverts a swapped file to it's original state (e.g. file.fswap -> file)
+  se[ssions] [ROOT]                     lists sessions registered on this machine, or found under ROOT
+  so[urce]  { add | list | remove }     manages named source directories
   s[wap]    [FILES...]                  if they both exist, swaps a file from SOURCE DIR to FSWAP DIR, and saves the swapped file
+  t[oggle]  [FILES...]                  flips swapped files between their original and swapped version
+  u[ndo]                                undoes the last change made to swapped files
+  w[atch]                               keeps swapped files in sync with their source until interrupted
 
 OPTIONS
   -a, --all          do command to all files in fswap directory
+      --absolute     store the source as an absolute path
+      --all-layers   revert stacked swaps all the way back to the original
+      --discard      end a session without reverting swapped files
+      --force        revert files without asking about edits made since the swap, or apply mismatched bundles
+      --from NAME    swap files in from source NAME
+      --from-rev REV swap files in from git revision REV
+      --git REV      begin a session with git revision REV as source
+      --patch FILE   swap in the files changed by patch FILE, or export one (without FILE)
   -h, --help         print this help, or help of another command
+      --keep-swapped end a session keeping the swapped versions
+      --merge        merge edits made since the swap into the reverted file
   -n, --noconfirm    will not ask for confirmation to overwrite files
+  -o, --output FILE  write to FILE instead of printing
+  -p, --partial      only swap in the hunks picked for each file
+      --rebackup     save the current file as the new original when swapping
   -r, --recursive    do command to all files in directory
-  -v, --verbose      prints everything given command does"#),
+      --session DIR  run command on the session in DIR
+      --since TIME   only print history since TIME
+      --snapshot     copy the source into the session when adding it
+      --until TIME   only print history until TIME
+      --revert-on-exit  revert all swapped files when watch stops
+      --swap-new     swap in files added to the source while watching
+      --template     store the source as given, expanding ~ and $VARS whenever it's used
+      --trash        move removed files to the trash instead of deleting them (--no-trash to not)
+  -v, --verbose      prints everything given command does
+      --verify       check the hashes of swapped files in the new source when relinking"#),
         _ => {
             eprintln!("ERROR: Cannot provide help for unknown command: {arg}");
             exit(1);
@@ -294,29 +591,81 @@ OPTIONS
 }
 
 fn cmd_end(u_input: &mut UserInput) -> bool {
-    let arg: String;
-    if u_input.argc > 0 {
-        arg = u_input.next_arg();
+    let arg = if u_input.argc > 0 {
+        u_input.next_arg()
     } else {
-        arg = String::from(".");
+        String::from(".")
+    };
+
+    let mut path = PathBuf::from(&arg);
+
+    if u_input.opts.keep_swapped && u_input.opts.discard {
+        eprintln!("ERROR: '--keep-swapped' and '--discard' can't be used together.");
+        exit(1);
     }
 
-    let path = PathBuf::from(&arg);
+    // Swapped files are reverted (or accepted) first, from the fswap directory itself since
+    // swaps are relative to it
+    let mut swapped: Vec<String> = vec![];
+    if !u_input.opts.discard && path.join(FSWAP_EXT).exists() {
+        check_dir(&path);
+        if let Err(err) = env::set_current_dir(&path) {
+            eprintln!("ERROR: Couldn't change to '{arg}': {err}");
+            exit(1);
+        }
+        path = PathBuf::from(".");
+
+        let session = Session::open(&path);
+        swapped = session.swaps.iter().map(|x| x.file.clone()).collect();
+    }
 
     if !u_input.opts.noconfirm {
-        let confirmed = confirm_cmd(&format!("Delete all files with the suffix '{FSWAP_EXT}'"));
-        if !confirmed {
+        let description = if u_input.opts.discard || swapped.is_empty() {
+            format!("Delete all files with the suffix '{FSWAP_EXT}'")
+        } else if u_input.opts.keep_swapped {
+            format!(
+                "Keep the swapped version of {n} file(s), delete their originals and end the session",
+                n = swapped.len()
+            )
+        } else {
+            format!(
+                "Revert {n} swapped file(s) and end the session",
+                n = swapped.len()
+            )
+        };
+        if !confirm_cmd(&description) {
             return true;
         }
     }
 
+    if !swapped.is_empty() {
+        let mut session = Session::open(&path);
+        u_input.opts.all_layers = true;
+        for file in &swapped {
+            if u_input.opts.keep_swapped {
+                accept_file(&mut session, file, &u_input.opts);
+            } else {
+                revert_file(&mut session, file, &u_input.opts);
+            }
+        }
+
+        // Edits kept by answering no when reverting
+        let left = session.swaps.len();
+        if left > 0 {
+            eprintln!(
+                "ERROR: {left} file(s) are still swapped, the session wasn't ended. Use --discard to end it anyway."
+            );
+            exit(1);
+        }
+    }
+
     let files = match find_files_with(&path, Some(FSWAP_EXT)) {
         Some(x) => x,
         None => return true,
     };
 
     for file in files {
-        if let Err(err) = fs::remove_file(&file) {
+        if let Err(err) = remove_file(Path::new(&file), &u_input.opts) {
             eprintln!("ERROR: Couldn't delete '{file}': {err}");
             exit(1);
         }
@@ -326,284 +675,2470 @@ fn cmd_end(u_input: &mut UserInput) -> bool {
         }
     }
 
-    return true;
-}
+    let store = path.join(FSWAP_STORE);
+    if store.exists() {
+        if let Err(err) = remove_dir(&store, &u_input.opts) {
+            eprintln!(
+                "ERROR: Couldn't delete '{dir}': {err}",
+                dir = store.display()
+            );
+            exit(1);
+        }
 
-fn cmd_swap(u_input: &mut UserInput) -> bool {
-    let mut fswap_file = File::open(FSWAP_EXT).unwrap_or_else(|err| {
-        eprintln!("ERROR: Couldn't open .fswap file: {err}");
-        exit(1);
-    });
+        if u_input.opts.verbose {
+            println!("INFO: Deleted '{dir}'.", dir = store.display());
+        }
+    }
 
-    // Read .fswap file to find src directory
-    let mut buf: [u8; 128] = [0; 128];
-    let sd_len = fswap_file.read(&mut buf).unwrap_or_else(|err| {
-        eprintln!("ERROR: Couldn't read .fswap file: {err}");
-        exit(1);
-    });
+    registry::remove(&path);
 
-    let sd = str::from_utf8(&buf).unwrap_or_else(|err| {
-        eprintln!("ERROR: Unhandleable str::from_utf8 error: {err}");
-        exit(1);
-    });
-    let source_dir = Path::new(&sd[0..sd_len]);
+    return true;
+}
 
-    if !source_dir.exists() {
-        eprintln!("ERROR: '{dir}' does not exist.", dir = source_dir.display());
-        exit(1);
+fn cmd_sessions(u_input: &mut UserInput) -> bool {
+    let root = if u_input.argc > 0 {
+        Some(u_input.next_arg())
+    } else {
+        None
+    };
+
+    for dir in registry::prune() {
+        if u_input.opts.verbose {
+            println!(
+                "INFO: Forgot the session in '{dir}', its {FSWAP_EXT} file is gone.",
+                dir = dir.display()
+            );
+        }
     }
 
-    let files: Vec<String>;
-    if u_input.opts.all {
-        let swapped_files = match find_files_with(&PathBuf::from("."), None) {
-            Some(x) => x,
-            None => {
-                eprintln!("No files found.");
-                return true;
+    // Directories of the sessions, with when they were begun if they're registered
+    let mut sessions: Vec<(PathBuf, Option<u64>)> = vec![];
+    match &root {
+        Some(root) => {
+            let root_dir = Path::new(root);
+            check_dir(root_dir);
+            let root_dir = fs::canonicalize(root_dir).unwrap_or(root_dir.to_path_buf());
+
+            for entry in registry::entries() {
+                if entry.dir.starts_with(&root_dir) {
+                    sessions.push((entry.dir, Some(entry.time)));
+                }
             }
-        };
 
-        // Filter out any fswap file
-        files = swapped_files
-            .into_iter()
-            .filter(|x| !x.contains(FSWAP_EXT))
-            .collect();
-    } else if u_input.opts.recursive {
-        let dirs = &u_input.args;
-        let mut out_files: Vec<String> = vec![];
-        for dir in dirs {
-            let swapped_files = match find_files_with(&PathBuf::from(&dir), None) {
-                Some(x) => x,
-                None => {
-                    eprintln!("No fswap files found.");
-                    return true;
+            for dir in find_sessions(&root_dir) {
+                if !sessions.iter().any(|x| x.0 == dir) {
+                    sessions.push((dir, None));
                 }
-            };
-
-            out_files = combine_string_vecs(&out_files, &swapped_files);
+            }
+        }
+        None => {
+            for entry in registry::entries() {
+                sessions.push((entry.dir, Some(entry.time)));
+            }
         }
-
-        // Filter out any fswap file
-        files = out_files
-            .into_iter()
-            .filter(|x| !x.contains(FSWAP_EXT))
-            .collect();
-    } else {
-        files = u_input.args.clone();
     }
+    sessions.sort_by(|a, b| a.0.cmp(&b.0));
 
-    let mut files = files.iter();
-    loop {
-        let arg = match files.next() {
+    if sessions.is_empty() {
+        match &root {
+            Some(root) => println!("No fswap sessions in '{root}'."),
+            None => println!("No fswap sessions registered."),
+        }
+        return true;
+    }
+
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |x| x.as_secs());
+
+    for (dir, begun) in sessions {
+        let session = match Session::read(&dir) {
+            Ok(x) => x,
+            Err(err) => {
+                eprintln!(
+                    "WARNING: Skipped the session in '{dir}': {err}",
+                    dir = dir.display()
+                );
+                continue;
+            }
+        };
+        let source = match session.sources.as_slice() {
+            [source] => match &source.rev {
+                Some(rev) => format!("git {rev}"),
+                None => source.path.clone(),
+            },
+            sources => format!("{n} sources", n = sources.len()),
+        };
+
+        // Unregistered sessions are as old as their .fswap file
+        let begun = begun.or_else(|| {
+            let md = dir.join(FSWAP_EXT).metadata().ok()?;
+            let time = md.created().or(md.modified()).ok()?;
+            time.duration_since(std::time::UNIX_EPOCH)
+                .ok()
+                .map(|x| x.as_secs())
+        });
+        let age = match begun {
+            Some(x) => format!("begun {age} ago", age = format_age(now.saturating_sub(x))),
+            None => String::from("begun at an unknown time"),
+        };
+
+        println!(
+            "  {dir}\t{source}\t({swapped} swapped, {age})",
+            dir = dir.display(),
+            swapped = session.swaps.len()
+        );
+    }
+
+    return true;
+}
+
+fn cmd_source(u_input: &mut UserInput) -> bool {
+    let action = if u_input.argc > 0 {
+        u_input.next_arg()
+    } else {
+        String::from("list")
+    };
+
+    let mut session = Session::open(Path::new("."));
+
+    match action.as_str() {
+        "add" => {
+            let name = u_input.next_arg();
+            let arg = u_input.next_arg();
+            if !session::valid_source_name(&name) {
+                eprintln!("ERROR: Invalid source name '{name}'.");
+                exit(1);
+            }
+
+            if session.source(&name).is_some() {
+                eprintln!("ERROR: Source '{name}' already exists.");
+                exit(1);
+            }
+
+            let mut source = Source {
+                name: name.clone(),
+                path: link_source(&arg, Path::new("."), &u_input.opts),
+                rev: None,
+                template: u_input.opts.template,
+            };
+            if u_input.opts.snapshot {
+                snapshot_source(&session, &mut source, u_input.opts.verbose);
+            }
+            let path = source.path.clone();
+
+            session.sources.push(source);
+            session.save();
+
+            if u_input.opts.verbose {
+                println!("INFO: Added source '{name}' with path '{path}'.");
+            }
+        }
+        "list" => {
+            for source in &session.sources {
+                let swapped = session
+                    .swaps
+                    .iter()
+                    .filter(|x| x.source == source.name)
+                    .count();
+                let path = match &source.rev {
+                    Some(rev) => format!("git {rev}"),
+                    None => source.path.clone(),
+                };
+                println!("  {name}\t{path}\t({swapped} swapped)", name = source.name);
+            }
+        }
+        "remove" => {
+            let name = u_input.next_arg();
+
+            if session.source(&name).is_none() {
+                eprintln!("ERROR: Unknown source '{name}'.");
+                exit(1);
+            }
+
+            // Also covered layers, reverting goes back to them
+            let in_use = session
+                .swaps
+                .iter()
+                .any(|x| x.source == name || x.under.iter().any(|layer| layer.source == name));
+            if in_use {
+                eprintln!("ERROR: Files are still swapped in from '{name}', revert them first.");
+                exit(1);
+            }
+
+            session.sources.retain(|x| x.name != name);
+            session.save();
+
+            if u_input.opts.verbose {
+                println!("INFO: Removed source '{name}'.");
+            }
+        }
+        _ => {
+            eprintln!("ERROR: Unknown source action '{action}'");
+            exit(1);
+        }
+    }
+
+    return true;
+}
+
+fn cmd_swap(u_input: &mut UserInput) -> bool {
+    let mut session = Session::open(Path::new("."));
+    let mut patch_files: Vec<String> = vec![];
+    if let Some(rev) = u_input.opts.from_rev.clone() {
+        if u_input.opts.from.is_some() {
+            eprintln!("ERROR: '--from' and '--from-rev' can't be used together.");
+            exit(1);
+        }
+        u_input.opts.from = Some(rev_source(&mut session, &rev));
+    }
+    if let Some(patch) = u_input.opts.patch.clone() {
+        if u_input.opts.from.is_some() {
+            eprintln!("ERROR: '--patch' can't be used together with '--from' or '--from-rev'.");
+            exit(1);
+        }
+        // Read before the source is added, so a patch that can't be swapped in changes nothing
+        if u_input.argc == 0 && !u_input.opts.all && !u_input.opts.recursive {
+            patch_files = patched_files(Path::new(&patch));
+        }
+        u_input.opts.from = Some(patch_source(&mut session, Path::new(&patch)));
+    }
+
+    let source = session.pick_source(u_input.opts.from.as_deref());
+    let source_name = source.name.clone();
+    let source_dir = session.source_dir(source);
+
+    if !source_dir.exists() {
+        eprintln!("ERROR: '{dir}' does not exist.", dir = source_dir.display());
+        exit(1);
+    }
+
+    let files: Vec<String>;
+    if u_input.opts.all {
+        let swapped_files = match find_files_with(&PathBuf::from("."), None) {
             Some(x) => x,
-            None => break,
+            None => {
+                eprintln!("No files found.");
+                return true;
+            }
+        };
+
+        // Filter out any fswap file
+        files = swapped_files
+            .into_iter()
+            .filter(|x| !x.contains(FSWAP_EXT))
+            .collect();
+    } else if u_input.opts.recursive {
+        let dirs = &u_input.args;
+        let mut out_files: Vec<String> = vec![];
+        for dir in dirs {
+            let swapped_files = match find_files_with(&PathBuf::from(&dir), None) {
+                Some(x) => x,
+                None => {
+                    eprintln!("No fswap files found.");
+                    return true;
+                }
+            };
+
+            out_files = combine_string_vecs(&out_files, &swapped_files);
+        }
+
+        // Filter out any fswap file
+        files = out_files
+            .into_iter()
+            .filter(|x| !x.contains(FSWAP_EXT))
+            .collect();
+    } else if u_input.argc == 0 && u_input.opts.patch.is_some() {
+        files = patch_files;
+    } else {
+        files = u_input.args.clone();
+    }
+
+    for arg in files.iter() {
+        swap_file(&mut session, &source_name, arg, &u_input.opts);
+    }
+
+    return true;
+}
+
+// text as a valid source name, anything else becomes '-'
+fn source_name(text: &str) -> String {
+    return text
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || "-_.".contains(c) {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect();
+}
+
+// Name of the source for git revision rev of the fswap directory, which is added if needed
+fn rev_source(session: &mut Session, rev: &str) -> String {
+    let name = source_name(rev);
+
+    if let Some(source) = session.source(&name) {
+        if source.rev.as_deref() != Some(rev) {
+            eprintln!("ERROR: Source '{name}' already exists, and isn't git revision '{rev}'.");
+            exit(1);
+        }
+        return name;
+    }
+
+    if !git::valid_rev(Path::new("."), rev) {
+        eprintln!("ERROR: '{rev}' isn't a git revision of the fswap directory.");
+        exit(1);
+    }
+
+    session.sources.push(Source {
+        name: name.clone(),
+        path: String::from("."),
+        rev: Some(rev.to_string()),
+        template: false,
+    });
+    session.save();
+    println!("Added source '{name}' for git revision '{rev}'.");
+
+    return name;
+}
+
+// Name of the source for the patch at path, which is added if needed
+fn patch_source(session: &mut Session, path: &Path) -> String {
+    if !diff::is_patch(path) || !path.is_file() {
+        eprintln!(
+            "ERROR: '{file}' isn't a .diff or .patch file.",
+            file = path.display()
+        );
+        exit(1);
+    }
+
+    let source = source_path(path, Path::new("."));
+    if let Some(existing) = session.sources.iter().find(|x| x.path == source) {
+        return existing.name.clone();
+    }
+
+    let name = source_name(&path.file_stem().unwrap_or_default().to_string_lossy());
+    if session.source(&name).is_some() {
+        eprintln!(
+            "ERROR: Source '{name}' already exists, add the patch with 'source add' instead."
+        );
+        exit(1);
+    }
+
+    session.sources.push(Source {
+        name: name.clone(),
+        path: source,
+        rev: None,
+        template: false,
+    });
+    session.save();
+    println!(
+        "Added source '{name}' for patch '{file}'.",
+        file = path.display()
+    );
+
+    return name;
+}
+
+// Files changed by the patch at path. Exits if it creates or deletes files, those can't be swapped
+fn patched_files(path: &Path) -> Vec<String> {
+    let text = fs::read_to_string(path).unwrap_or_else(|err| {
+        eprintln!(
+            "ERROR: Couldn't read '{file}': {err}",
+            file = path.display()
+        );
+        exit(1);
+    });
+    let patches = diff::parse_patch(&text).unwrap_or_else(|line| {
+        eprintln!(
+            "ERROR: '{file}' line {line}: malformed hunk.",
+            file = path.display()
+        );
+        exit(1);
+    });
+
+    let mut files: Vec<String> = vec![];
+    let mut failed = false;
+    for patch in patches {
+        match (patch.old_path, patch.new_path) {
+            (Some(_), Some(file)) => files.push(file),
+            (None, Some(file)) => {
+                eprintln!("ERROR: The patch creates '{file}', only existing files can be swapped.");
+                failed = true;
+            }
+            (Some(file), None) => {
+                eprintln!("ERROR: The patch deletes '{file}', only existing files can be swapped.");
+                failed = true;
+            }
+            (None, None) => {}
+        }
+    }
+
+    if failed {
+        exit(1);
+    }
+    return files;
+}
+
+// Swaps file in from source_name, stacking it on top if file is already swapped
+fn swap_file(session: &mut Session, source_name: &str, file: &str, opts: &Opts) {
+    let source = session.pick_source(Some(source_name));
+    let source_file = session.source_file(source, file);
+
+    let working_file = PathBuf::from(file);
+
+    if !working_file.exists() {
+        eprintln!(
+            "ERROR: '{file}' doesn't exist.",
+            file = working_file.display()
+        );
+        exit(1);
+    }
+
+    let working_md = working_file.metadata().unwrap_or_else(|err| {
+        eprintln!(
+            "ERROR: Couldn't get metadata from '{file}': {err}",
+            file = working_file.display()
+        );
+        exit(1);
+    });
+
+    if !working_md.file_type().is_file() {
+        eprintln!(
+            "ERROR: '{file}' isn't a normal file.",
+            file = working_file.display()
+        );
+        exit(1);
+    }
+
+    if !source_file.exists() {
+        eprintln!(
+            "ERROR: '{file}' does not exist.",
+            file = source_file.display()
+        );
+        exit(1);
+    }
+
+    let swapped_file = append_to_pathbuf(&working_file, FSWAP_EXT);
+    let key = session::normalize(file);
+    let hash = hash_file(&source_file);
+    let before = hash_file(&working_file);
+
+    // Already swapped in from some source, the backup holds the original so it's left alone.
+    // Swapping from another source covers the current version, which is kept as a layer
+    let tracked = session.swap(&key).is_some() && swapped_file.exists();
+
+    // Swapping from the same source again copies over the current version, so ask first if it
+    // was edited. Toggled files keep it as file.fswap
+    if let Some(swap) = session.swap(&key).filter(|_| tracked)
+        && swap.source == source_name
+        && !opts.rebackup
+        && !opts.partial
+        && !opts.noconfirm
+    {
+        let current_file = if swap.toggled {
+            &swapped_file
+        } else {
+            &working_file
+        };
+        let current = hash_file(current_file);
+        if current != hash && swap.swapped_hash() != Some(&current) {
+            let confirmed = confirm_cmd(&format!(
+                "'{file}' was edited since it was swapped, overwrite it",
+                file = current_file.display()
+            ));
+            if !confirmed {
+                println!("Skipped '{key}'.");
+                return;
+            }
+        }
+    }
+
+    // Hunks are picked from the current swapped version, before anything is changed
+    let toggled = tracked && session.swap(&key).is_some_and(|x| x.toggled);
+    let partial = if opts.partial {
+        let current_file = if toggled {
+            &swapped_file
+        } else {
+            &working_file
+        };
+        match pick_hunks(current_file, &source_file) {
+            Some(x) => Some(x),
+            None => {
+                println!("Nothing swapped in for '{key}'.");
+                return;
+            }
+        }
+    } else {
+        None
+    };
+
+    undo::record(session, &key);
+
+    if toggled {
+        flip_toggle(session, &key);
+        session.save();
+    }
+
+    if tracked {
+        let swap = session
+            .swap(&key)
+            .expect("tracked files have a swap record");
+        if opts.rebackup {
+            // The current version becomes the new original, so older layers are meaningless
+            drop_layers(session, &key, opts);
+
+            if let Err(err) = fs::copy(&working_file, &swapped_file) {
+                eprintln!(
+                    "ERROR: Couldn't copy '{src}' to '{dest}': {err}",
+                    src = working_file.display(),
+                    dest = swapped_file.display()
+                );
+                exit(1);
+            }
+
+            if opts.verbose {
+                println!(
+                    "INFO: Copied '{src}' -> '{dest}'.",
+                    src = working_file.display(),
+                    dest = swapped_file.display()
+                );
+            }
+        } else if swap.source != source_name {
+            let layer_file = session.layer_path(&key, swap.under.len() + 1);
+            store_file(&working_file, &layer_file);
+
+            let swap = session
+                .swap_mut(&key)
+                .expect("tracked files have a swap record");
+            swap.push_layer(source_name, &hash);
+
+            if opts.verbose {
+                println!(
+                    "INFO: Renamed '{src}' -> '{dest}'.",
+                    src = working_file.display(),
+                    dest = layer_file.display()
+                );
+            }
+        }
+    } else {
+        // Not in the session, so there is no telling what file.fswap holds, it could be the
+        // only copy of the original
+        if !opts.rebackup && swapped_file.exists() {
+            eprintln!(
+                "ERROR: '{file}' already exists, but '{key}' isn't swapped. Use --rebackup to replace it.",
+                file = swapped_file.display()
+            );
+            exit(1);
+        }
+
+        if let Err(err) = fs::rename(&working_file, &swapped_file) {
+            eprintln!(
+                "ERROR: Couldn't rename '{src}' to '{dest}': {err}",
+                src = working_file.display(),
+                dest = swapped_file.display()
+            );
+            exit(1);
+        }
+
+        if opts.verbose {
+            println!(
+                "INFO: Renamed '{src}' -> '{dest}'.",
+                src = working_file.display(),
+                dest = swapped_file.display()
+            );
+        }
+    }
+
+    let content = match partial {
+        Some(content) => {
+            if let Err(err) = fs::write(&working_file, &content) {
+                eprintln!(
+                    "ERROR: Couldn't write to '{file}': {err}",
+                    file = working_file.display()
+                );
+                exit(1);
+            }
+
+            if opts.verbose {
+                println!(
+                    "INFO: Wrote picked hunks of '{src}' to '{dest}'.",
+                    src = source_file.display(),
+                    dest = working_file.display()
+                );
+            }
+            Some(hash_bytes(content.as_bytes())).filter(|x| *x != hash)
+        }
+        None => {
+            if let Err(err) = fs::copy(&source_file, &working_file) {
+                eprintln!(
+                    "ERROR: Couldn't copy '{src}' to '{dest}': {err}",
+                    src = source_file.display(),
+                    dest = working_file.display()
+                );
+                exit(1);
+            }
+
+            if opts.verbose {
+                println!(
+                    "INFO: Copied '{src}' -> '{dest}'.",
+                    src = source_file.display(),
+                    dest = working_file.display()
+                );
+            }
+            None
+        }
+    };
+
+    session.set_swap(&key, source_name, &hash);
+    if let Some(swap) = session.swap_mut(&key) {
+        swap.content = content;
+    }
+    session.save();
+    session.log("swap", &key, Some(&before), Some(&hash_file(&working_file)));
+}
+
+// Asks about every hunk between working_file and source_file, and returns working_file with only
+// the picked hunks from source_file. Returns None if nothing was picked
+fn pick_hunks(working_file: &Path, source_file: &Path) -> Option<String> {
+    let read = |path: &Path| -> String {
+        fs::read_to_string(path).unwrap_or_else(|err| {
+            eprintln!(
+                "ERROR: Couldn't read '{file}' as text: {err}",
+                file = path.display()
+            );
+            exit(1);
+        })
+    };
+
+    let working = read(working_file);
+    let source = read(source_file);
+    let a = diff::lines(&working);
+    let b = diff::lines(&source);
+    let hunks = diff::diff(&a, &b);
+
+    println!(
+        "--- {a}\n+++ {b}",
+        a = working_file.display(),
+        b = source_file.display()
+    );
+
+    let mut out = String::new();
+    let mut pos = 0;
+    let mut picked = 0;
+    for (n, hunk) in hunks.iter().enumerate() {
+        a[pos..hunk.a_start].iter().for_each(|x| out.push_str(x));
+        pos = hunk.a_start + hunk.a_len;
+
+        print!("{}", diff::format_hunk(&a, &b, hunk, 3));
+        if confirm_cmd(&format!(
+            "Swap this hunk ({n}/{total})",
+            n = n + 1,
+            total = hunks.len()
+        )) {
+            b[hunk.b_start..hunk.b_start + hunk.b_len]
+                .iter()
+                .for_each(|x| out.push_str(x));
+            picked += 1;
+        } else {
+            a[hunk.a_start..pos].iter().for_each(|x| out.push_str(x));
+        }
+    }
+    a[pos..].iter().for_each(|x| out.push_str(x));
+
+    if picked == 0 && !hunks.is_empty() {
+        return None;
+    }
+
+    return Some(out);
+}
+
+fn cmd_revert(u_input: &mut UserInput) -> bool {
+    let mut session = Session::open(Path::new("."));
+
+    let files: Vec<String>;
+    if u_input.opts.all {
+        let swapped_files = match find_files_with(&PathBuf::from("."), Some(FSWAP_EXT)) {
+            Some(x) => x,
+            None => {
+                eprintln!("No fswap files found.");
+                return true;
+            }
+        };
+
+        files = swapped_files
+            .iter()
+            .map(|x| {
+                x.strip_suffix(FSWAP_EXT)
+                    .expect("Files that contain .fswap should always have it as a suffix")
+                    .to_string()
+            })
+            .filter(|x| !x.eq("./"))
+            .collect();
+    } else if u_input.opts.recursive {
+        let dirs = &u_input.args;
+        let mut out_files: Vec<String> = vec![];
+        for dir in dirs {
+            let swapped_files = match find_files_with(&PathBuf::from(&dir), Some(FSWAP_EXT)) {
+                Some(x) => x,
+                None => {
+                    eprintln!("No fswap files found.");
+                    return true;
+                }
+            };
+
+            let cur_files: Vec<String> = swapped_files
+                .iter()
+                .map(|x| {
+                    x.strip_suffix(FSWAP_EXT)
+                        .expect("Files that contain .fswap should always have it as a suffix")
+                        .to_string()
+                })
+                .filter(|x| !x.eq("./"))
+                .collect();
+            out_files = combine_string_vecs(&out_files, &cur_files);
+        }
+
+        files = out_files;
+    } else {
+        files = u_input.args.clone();
+    }
+
+    for file in files.iter() {
+        revert_file(&mut session, file, &u_input.opts);
+    }
+
+    return true;
+}
+
+// Reverts file one layer, or back to the original
+fn revert_file(session: &mut Session, file: &str, opts: &Opts) {
+    // Misleading name, in this case source_file actually refers to the file that was swapped
+    // in FROM the source directory, not a file in the source directory
+    let source_file = PathBuf::from(&file);
+    let key = session::normalize(file);
+
+    let working_file = append_to_pathbuf(&source_file, FSWAP_EXT);
+    if !working_file.exists() {
+        eprintln!(
+            "ERROR: '{file}' isn't swapped, '{swapped}' doesn't exist.",
+            swapped = working_file.display()
+        );
+        exit(1);
+    }
+
+    // Toggled files have the original in place until they're flipped back, which only happens
+    // once the edits are dealt with, so a skipped revert leaves everything as it was
+    let toggled = session.swap(&key).is_some_and(|x| x.toggled);
+    let (swapped_file, original_file) = if toggled {
+        (working_file.clone(), source_file.clone())
+    } else {
+        (source_file.clone(), working_file.clone())
+    };
+
+    // Swapped on top of other sources, pop the top layer unless asked to go all the way back
+    let layers = session.swap(&key).map_or(0, |x| x.under.len());
+    let pop_layer = layers > 0 && !opts.all_layers;
+
+    let merged = if opts.merge {
+        let target = if pop_layer {
+            session.layer_path(&key, layers)
+        } else {
+            original_file
+        };
+        merge_edits(session, &key, &swapped_file, &target)
+    } else {
+        None
+    };
+
+    if merged.is_none() && !opts.force && !resolve_edits(session, &key, &swapped_file) {
+        return;
+    }
+
+    undo::record(session, &key);
+
+    if toggled {
+        flip_toggle(session, &key);
+        session.save();
+    }
+
+    let before = source_file.exists().then(|| hash_file(&source_file));
+
+    if pop_layer {
+        let layer_file = session.layer_path(&key, layers);
+        rename_file(&layer_file, &source_file);
+        write_merged(&source_file, merged);
+
+        let swap = session
+            .swap_mut(&key)
+            .expect("layers only exist on swapped files");
+        swap.pop_layer();
+        let name = swap.source.clone();
+        session.save();
+        session.log(
+            "revert",
+            &key,
+            before.as_deref(),
+            Some(&hash_file(&source_file)),
+        );
+
+        if opts.verbose {
+            println!(
+                "INFO: Renamed '{src}' -> '{dest}', back to source '{name}'.",
+                src = layer_file.display(),
+                dest = source_file.display()
+            );
+        }
+        return;
+    }
+
+    drop_layers(session, &key, opts);
+
+    if source_file.exists()
+        && let Err(err) = remove_file(&source_file, opts)
+    {
+        eprintln!(
+            "ERROR: couldn't remove '{file}': {err}",
+            file = source_file.display()
+        );
+        exit(1);
+    }
+
+    if let Err(err) = fs::rename(&working_file, &source_file) {
+        eprintln!(
+            "ERROR: Couldn't rename '{work_file}' to '{src_file}': {err}",
+            work_file = working_file.display(),
+            src_file = source_file.display()
+        );
+        exit(1);
+    }
+
+    write_merged(&source_file, merged);
+    session.remove_swap(&key);
+    session.save();
+    session.log(
+        "revert",
+        &key,
+        before.as_deref(),
+        Some(&hash_file(&source_file)),
+    );
+
+    if opts.verbose {
+        println!("INFO: Removed '{file}'.", file = source_file.display());
+        println!(
+            "INFO: Renamed '{src}' -> '{dest}'.",
+            src = working_file.display(),
+            dest = source_file.display()
+        );
+    }
+}
+
+// Carries the edits made to file since it was swapped over to target, the version reverting
+// goes back to. working_file is where the swapped version is, file or file.fswap if toggled.
+// Returns None if file wasn't edited
+fn merge_edits(
+    session: &Session,
+    file: &str,
+    working_file: &Path,
+    target: &Path,
+) -> Option<String> {
+    let swap = session.swap(file)?;
+    if hash_file(working_file) == *swap.swapped_hash()? {
+        return None;
+    }
+
+    if swap.content.is_some() {
+        eprintln!("ERROR: '{file}' was only partially swapped in, can't merge it.");
+        exit(1);
+    }
+
+    // The version that was swapped in is the base, which is only around while the source is
+    // unchanged
+    let source = session.pick_source(Some(&swap.source));
+    let source_file = session.source_file(source, file);
+    if !source_file.exists() || Some(&hash_file(&source_file)) != swap.hash.as_ref() {
+        eprintln!(
+            "ERROR: '{file}' changed since '{work}' was swapped, can't merge without it.",
+            file = source_file.display(),
+            work = working_file.display()
+        );
+        exit(1);
+    }
+
+    let read = |path: &Path| -> String {
+        fs::read_to_string(path).unwrap_or_else(|err| {
+            eprintln!(
+                "ERROR: Couldn't read '{file}' as text: {err}",
+                file = path.display()
+            );
+            exit(1);
+        })
+    };
+
+    let (merged, conflicts) = diff::merge3(
+        &read(&source_file),
+        &read(working_file),
+        &read(target),
+        &format!("{file} (edited)"),
+        &format!("{target} (reverted)", target = target.display()),
+    );
+
+    if conflicts > 0 {
+        println!("Merged edits to '{file}' with {conflicts} conflict(s).");
+    } else {
+        println!("Merged edits to '{file}'.");
+    }
+
+    return Some(merged);
+}
+
+fn write_merged(path: &Path, merged: Option<String>) {
+    if let Some(merged) = merged
+        && let Err(err) = fs::write(path, merged)
+    {
+        eprintln!(
+            "ERROR: Couldn't write to '{file}': {err}",
+            file = path.display()
+        );
+        exit(1);
+    }
+}
+
+// Reverting deletes the swapped in file, so edits made since the swap are dealt with first.
+// Returns false if the revert should be skipped
+fn resolve_edits(session: &Session, file: &str, working_file: &Path) -> bool {
+    let swap = match session.swap(file) {
+        Some(x) => x,
+        None => return true,
+    };
+
+    let recorded = match swap.swapped_hash() {
+        Some(x) => x,
+        None => return true,
+    };
+    if !working_file.exists() || hash_file(working_file) == *recorded {
+        return true;
+    }
+
+    let side_file = append_to_pathbuf(Path::new(file), ".rej");
+    let choice = choose_cmd(
+        &format!(
+            "'{file}' was edited since it was swapped, (k)eep the edits as '{side}', (p)ush them to source '{name}' or (d)iscard them",
+            side = side_file.display(),
+            name = swap.source
+        ),
+        &['k', 'p', 'd'],
+    );
+
+    match choice {
+        Some('k') => {
+            if side_file.exists() {
+                println!(
+                    "'{side}' already exists, skipped '{file}'.",
+                    side = side_file.display()
+                );
+                return false;
+            }
+            if let Err(err) = fs::copy(working_file, &side_file) {
+                eprintln!(
+                    "ERROR: Couldn't copy '{src}' to '{dest}': {err}",
+                    src = working_file.display(),
+                    dest = side_file.display()
+                );
+                exit(1);
+            }
+            println!(
+                "Kept edits to '{file}' in '{side}'.",
+                side = side_file.display()
+            );
+        }
+        Some('p') => {
+            let source = session.pick_source(Some(&swap.source));
+            if !source.is_dir() {
+                println!(
+                    "Source '{name}' can't be written to, skipped '{file}'.",
+                    name = swap.source
+                );
+                return false;
+            }
+            let source_file = session.source_dir(source).join(file);
+            if !confirm_push(swap, &source_file, working_file, false) {
+                println!("Skipped '{file}'.");
+                return false;
+            }
+            let before = source_file.exists().then(|| hash_file(&source_file));
+            undo::record_path(session, &source_file);
+            if let Err(err) = fs::copy(working_file, &source_file) {
+                eprintln!(
+                    "ERROR: Couldn't copy '{src}' to '{dest}': {err}",
+                    src = working_file.display(),
+                    dest = source_file.display()
+                );
+                exit(1);
+            }
+            println!("Pushed '{file}' to source '{name}'.", name = swap.source);
+            session.log(
+                "push",
+                file,
+                before.as_deref(),
+                Some(&hash_file(working_file)),
+            );
+        }
+        Some('d') => {}
+        _ => {
+            println!("Skipped '{file}'.");
+            return false;
+        }
+    }
+
+    return true;
+}
+
+fn cmd_toggle(u_input: &mut UserInput) -> bool {
+    let mut session = Session::open(Path::new("."));
+
+    let files = picked_files(&session, u_input);
+
+    if files.is_empty() {
+        eprintln!("No swapped files found.");
+        return true;
+    }
+
+    for file in files {
+        let toggled = match session.swap(&file) {
+            Some(swap) => swap.toggled,
+            None => {
+                eprintln!("ERROR: '{file}' isn't swapped.");
+                exit(1);
+            }
+        };
+
+        let before = hash_file(Path::new(&file));
+        undo::record(&session, &file);
+        flip_toggle(&mut session, &file);
+        session.save();
+        session.log(
+            "toggle",
+            &file,
+            Some(&before),
+            Some(&hash_file(Path::new(&file))),
+        );
+
+        if u_input.opts.verbose {
+            let state = if toggled { "swapped" } else { "original" };
+            println!("INFO: Toggled '{file}' to {state}.");
+        }
+    }
+
+    return true;
+}
+
+// Swaps file and file.fswap around, nothing is copied so both versions keep any edits
+fn flip_toggle(session: &mut Session, file: &str) {
+    let working_file = PathBuf::from(file);
+    let swapped_file = append_to_pathbuf(&working_file, FSWAP_EXT);
+    let tmp_file = append_to_pathbuf(&working_file, ".fswap-toggle");
+
+    if !swapped_file.exists() {
+        eprintln!(
+            "ERROR: '{file}' doesn't exist.",
+            file = swapped_file.display()
+        );
+        exit(1);
+    }
+
+    rename_file(&working_file, &tmp_file);
+    rename_file(&swapped_file, &working_file);
+    rename_file(&tmp_file, &swapped_file);
+
+    if let Some(swap) = session.swap_mut(file) {
+        swap.toggled = !swap.toggled;
+    }
+}
+
+// Deletes path, or moves it to the trash with --trash
+fn remove_file(path: &Path, opts: &Opts) -> io::Result<()> {
+    if opts.trash {
+        return trash::trash(path);
+    }
+    return fs::remove_file(path);
+}
+
+fn remove_dir(path: &Path, opts: &Opts) -> io::Result<()> {
+    if opts.trash {
+        return trash::trash(path);
+    }
+    return fs::remove_dir_all(path);
+}
+
+// Deletes every version of file kept under its top layer
+fn drop_layers(session: &mut Session, file: &str, opts: &Opts) {
+    let layers = session.swap(file).map_or(0, |x| x.under.len());
+    for n in 1..=layers {
+        let layer_file = session.layer_path(file, n);
+        if let Err(err) = remove_file(&layer_file, opts) {
+            eprintln!(
+                "ERROR: couldn't remove '{file}': {err}",
+                file = layer_file.display()
+            );
+            exit(1);
+        }
+
+        if opts.verbose {
+            println!("INFO: Removed '{file}'.", file = layer_file.display());
+        }
+    }
+
+    if let Some(swap) = session.swap_mut(file) {
+        swap.under.clear();
+    }
+}
+
+// Copies source into the store and points it there, so later changes to it don't matter. git
+// revisions are pinned to the commit they point to instead
+fn snapshot_source(session: &Session, source: &mut Source, verbose: bool) {
+    if let Some(rev) = &source.rev {
+        let commit = git::resolve(&session.source_dir(source), rev);
+        if verbose {
+            println!(
+                "INFO: Pinned source '{name}' to commit {commit}.",
+                name = source.name
+            );
+        }
+        source.rev = Some(commit);
+        return;
+    }
+
+    let src = session.source_dir(source);
+    let mut dest = Path::new(FSWAP_STORE).join("snapshots").join(&source.name);
+    if session.dir.join(&dest).exists() {
+        eprintln!(
+            "ERROR: '{dir}' already exists.",
+            dir = session.dir.join(&dest).display()
+        );
+        exit(1);
+    }
+
+    // Archives and patches keep their name, it tells what they are
+    if src.is_file() {
+        dest.push(src.file_name().unwrap_or_default());
+        copy_file(&src, &session.dir.join(&dest));
+    } else {
+        copy_tree(&src, &session.dir.join(&dest));
+    }
+
+    if verbose {
+        println!(
+            "INFO: Copied source '{name}' from '{src}' to '{dest}'.",
+            name = source.name,
+            src = src.display(),
+            dest = dest.display()
+        );
+    }
+    source.path = dest.display().to_string();
+    source.template = false;
+}
+
+fn copy_tree(src: &Path, dest: &Path) {
+    let entries = fs::read_dir(src).unwrap_or_else(|err| {
+        eprintln!(
+            "ERROR: Couldn't read dir '{dir}': {err}",
+            dir = src.display()
+        );
+        exit(1);
+    });
+
+    if let Err(err) = fs::create_dir_all(dest) {
+        eprintln!(
+            "ERROR: Couldn't create '{dir}': {err}",
+            dir = dest.display()
+        );
+        exit(1);
+    }
+
+    for entry in entries.flatten() {
+        // Also keeps a snapshot from being copied into itself
+        if entry.file_name() == FSWAP_STORE {
+            continue;
+        }
+
+        let path = entry.path();
+        if path.is_dir() {
+            copy_tree(&path, &dest.join(entry.file_name()));
+        } else if path.is_file() {
+            copy_file(&path, &dest.join(entry.file_name()));
+        }
+    }
+}
+
+// Shares the data with src where the filesystem can (reflinks on btrfs, xfs, ...), copies it
+// otherwise
+fn copy_file(src: &Path, dest: &Path) {
+    if let Some(parent) = dest.parent()
+        && let Err(err) = fs::create_dir_all(parent)
+    {
+        eprintln!(
+            "ERROR: Couldn't create '{dir}': {err}",
+            dir = parent.display()
+        );
+        exit(1);
+    }
+
+    let cloned = File::open(src).and_then(|from| {
+        let to = File::create(dest)?;
+        let ret = unsafe { libc::ioctl(to.as_raw_fd(), libc::FICLONE, from.as_raw_fd()) };
+        Ok(ret == 0)
+    });
+
+    if !matches!(cloned, Ok(true))
+        && let Err(err) = fs::copy(src, dest)
+    {
+        eprintln!(
+            "ERROR: Couldn't copy '{src}' to '{dest}': {err}",
+            src = src.display(),
+            dest = dest.display()
+        );
+        exit(1);
+    }
+}
+
+// Moves src into the store, creating any missing directories
+fn store_file(src: &Path, dest: &Path) {
+    if let Some(parent) = dest.parent()
+        && let Err(err) = fs::create_dir_all(parent)
+    {
+        eprintln!(
+            "ERROR: Couldn't create '{dir}': {err}",
+            dir = parent.display()
+        );
+        exit(1);
+    }
+
+    rename_file(src, dest);
+}
+
+fn rename_file(src: &Path, dest: &Path) {
+    if let Err(err) = fs::rename(src, dest) {
+        eprintln!(
+            "ERROR: Couldn't rename '{src}' to '{dest}': {err}",
+            src = src.display(),
+            dest = dest.display()
+        );
+        exit(1);
+    }
+}
+
+fn cmd_refresh(u_input: &mut UserInput) -> bool {
+    let mut session = Session::open(Path::new("."));
+
+    let files = picked_files(&session, u_input);
+
+    let mut updated = 0;
+    for file in files {
+        if refresh_file(&mut session, &file, &u_input.opts) {
+            updated += 1;
+        }
+    }
+
+    if updated == 0 {
+        println!("Nothing to refresh.");
+    }
+
+    return true;
+}
+
+// Copies file from its source again if the source changed, returns whether file was updated
+fn refresh_file(session: &mut Session, file: &str, opts: &Opts) -> bool {
+    let swap = session.swap(file).unwrap_or_else(|| {
+        eprintln!("ERROR: '{file}' isn't swapped.");
+        exit(1);
+    });
+
+    let source = session.source(&swap.source).unwrap_or_else(|| {
+        eprintln!("ERROR: Unknown source '{name}'.", name = swap.source);
+        exit(1);
+    });
+
+    let source_file = session.source_file(source, file);
+    if !source_file.exists() {
+        eprintln!(
+            "ERROR: '{file}' does not exist.",
+            file = source_file.display()
+        );
+        exit(1);
+    }
+
+    let hash = hash_file(&source_file);
+    if swap.hash.as_ref() == Some(&hash) {
+        return false;
+    }
+
+    // Toggled files keep the swapped in version as file.fswap
+    let working_file = if swap.toggled {
+        append_to_pathbuf(Path::new(file), FSWAP_EXT)
+    } else {
+        PathBuf::from(file)
+    };
+
+    let current = hash_file(&working_file);
+    if current != hash && swap.swapped_hash() != Some(&current) && !opts.noconfirm {
+        let confirmed = confirm_cmd(&format!(
+            "'{file}' was edited since it was swapped, overwrite it",
+            file = working_file.display()
+        ));
+        if !confirmed {
+            return false;
+        }
+    }
+
+    undo::record(session, file);
+    let updated = current != hash;
+    if updated {
+        if let Err(err) = fs::copy(&source_file, &working_file) {
+            eprintln!(
+                "ERROR: Couldn't copy '{src}' to '{dest}': {err}",
+                src = source_file.display(),
+                dest = working_file.display()
+            );
+            exit(1);
+        }
+
+        println!("Updated '{file}' from source '{name}'.", name = swap.source);
+    }
+
+    if let Some(swap) = session.swap_mut(file) {
+        swap.hash = Some(hash.clone());
+        swap.content = None;
+    }
+    session.save();
+
+    if updated {
+        session.log("refresh", file, Some(&current), Some(&hash));
+    }
+
+    return updated;
+}
+
+fn cmd_log(u_input: &mut UserInput) -> bool {
+    let session = Session::open(Path::new("."));
+    let files: Vec<String> = u_input.args.iter().map(|x| session::normalize(x)).collect();
+    let since = u_input.opts.since.as_deref().map(parse_time);
+    let until = u_input.opts.until.as_deref().map(parse_time);
+
+    let short = |hash: &Option<String>| match hash {
+        Some(x) => x[..8.min(x.len())].to_string(),
+        None => String::from("-"),
+    };
+
+    let mut batch = String::new();
+    let mut shown = 0;
+    for entry in session.history().iter().rev() {
+        if (!files.is_empty() && !files.contains(&entry.file))
+            || since.is_some_and(|x| entry.time < x)
+            || until.is_some_and(|x| entry.time > x)
+        {
+            continue;
+        }
+
+        if shown > 0 && entry.batch != batch {
+            println!();
+        }
+        batch = entry.batch.clone();
+        shown += 1;
+
+        println!(
+            "{time}  {user}  {command:<8} {file}  {before} -> {after}",
+            time = format_time(entry.time),
+            user = entry.user,
+            command = entry.command,
+            file = entry.file,
+            before = short(&entry.before),
+            after = short(&entry.after)
+        );
+    }
+
+    if shown == 0 {
+        println!("No history.");
+    }
+
+    return true;
+}
+
+fn cmd_push(u_input: &mut UserInput) -> bool {
+    let mut session = Session::open(Path::new("."));
+
+    let files = picked_files(&session, u_input);
+
+    let mut pushed = 0;
+    for file in files {
+        let swap = session.swap(&file).unwrap_or_else(|| {
+            eprintln!("ERROR: '{file}' isn't swapped.");
+            exit(1);
+        });
+
+        let source = session.source(&swap.source).unwrap_or_else(|| {
+            eprintln!("ERROR: Unknown source '{name}'.", name = swap.source);
+            exit(1);
+        });
+
+        if !source.is_dir() {
+            eprintln!(
+                "ERROR: Source '{name}' isn't a directory, '{file}' can't be pushed to it.",
+                name = swap.source
+            );
+            exit(1);
+        }
+
+        let source_file = session.source_dir(source).join(&file);
+        let working_file = if swap.toggled {
+            append_to_pathbuf(Path::new(&file), FSWAP_EXT)
+        } else {
+            PathBuf::from(&file)
         };
 
-        let working_file = PathBuf::from(&arg);
-        let mut source_file = PathBuf::from(source_dir);
-        source_file.push(&working_file);
+        let current = hash_file(&working_file);
+        if swap.swapped_hash() == Some(&current) {
+            continue;
+        }
+
+        if !confirm_push(swap, &source_file, &working_file, u_input.opts.noconfirm) {
+            continue;
+        }
 
-        if !working_file.exists() {
+        let before = source_file.exists().then(|| hash_file(&source_file));
+        undo::record(&session, &file);
+        undo::record_path(&session, &source_file);
+        if let Err(err) = fs::copy(&working_file, &source_file) {
             eprintln!(
-                "ERROR: '{file}' doesn't exist.",
-                file = working_file.display()
+                "ERROR: Couldn't copy '{src}' to '{dest}': {err}",
+                src = working_file.display(),
+                dest = source_file.display()
             );
             exit(1);
         }
 
-        let working_md = working_file.metadata().unwrap_or_else(|err| {
+        println!("Pushed '{file}' to source '{name}'.", name = swap.source);
+        pushed += 1;
+
+        if let Some(swap) = session.swap_mut(&file) {
+            swap.hash = Some(current.clone());
+            swap.content = None;
+        }
+        session.save();
+        session.log("push", &file, before.as_deref(), Some(&current));
+    }
+
+    if pushed == 0 {
+        println!("Nothing to push.");
+    }
+
+    return true;
+}
+
+// Both sides changed since the swap, pushing would throw away the changes in the source. Returns
+// false if it shouldn't be pushed
+fn confirm_push(
+    swap: &session::Swap,
+    source_file: &Path,
+    working_file: &Path,
+    noconfirm: bool,
+) -> bool {
+    if !source_file.exists() || swap.hash.as_ref() == Some(&hash_file(source_file)) || noconfirm {
+        return true;
+    }
+    return confirm_cmd(&format!(
+        "'{file}' changed since '{work}' was swapped, overwrite it",
+        file = source_file.display(),
+        work = working_file.display()
+    ));
+}
+
+fn cmd_watch(u_input: &mut UserInput) -> bool {
+    let session = Session::open(Path::new("."));
+
+    // New files are only swapped in from one source
+    let new_source = if u_input.opts.swap_new {
+        Some(
+            session
+                .pick_source(u_input.opts.from.as_deref())
+                .name
+                .clone(),
+        )
+    } else {
+        None
+    };
+
+    let mut watcher = watch::Watcher::new();
+    let mut dirs: Vec<(String, PathBuf)> = vec![];
+    for source in &session.sources {
+        // Only directories are watched, revisions and archives are left alone
+        if !source.is_dir() {
+            continue;
+        }
+
+        let dir = session.source_dir(source);
+        check_dir(&dir);
+        watcher.add_tree(&dir);
+        dirs.push((source.name.clone(), dir));
+    }
+
+    watch::catch_interrupt();
+    println!(
+        "Watching {n} source(s), press Ctrl-C to stop.",
+        n = dirs.len()
+    );
+
+    let mut pending: Vec<PathBuf> = vec![];
+    while !watch::interrupted() {
+        let timeout = if pending.is_empty() {
+            -1
+        } else {
+            WATCH_DEBOUNCE_MS
+        };
+
+        let changed = watcher.wait(timeout);
+        if !changed.is_empty() {
+            changed.into_iter().for_each(|x| {
+                if !pending.contains(&x) {
+                    pending.push(x);
+                }
+            });
+            continue;
+        }
+
+        if pending.is_empty() || watch::interrupted() {
+            continue;
+        }
+
+        // Other fswap commands may have run in the meantime
+        let mut session = Session::open(Path::new("."));
+        for path in pending.drain(..) {
+            for (name, dir) in &dirs {
+                let file = match path.strip_prefix(dir) {
+                    Ok(x) => session::normalize(&x.display().to_string()),
+                    Err(_) => continue,
+                };
+
+                match session.swap(&file) {
+                    Some(swap) if swap.source == *name => {
+                        refresh_file(&mut session, &file, &u_input.opts);
+                    }
+                    Some(_) => {}
+                    None if new_source.as_ref() == Some(name) && Path::new(&file).is_file() => {
+                        swap_file(&mut session, name, &file, &u_input.opts);
+                        println!("Swapped '{file}' from source '{name}'.");
+                    }
+                    None => {}
+                }
+            }
+        }
+    }
+
+    if u_input.opts.revert_on_exit {
+        let mut session = Session::open(Path::new("."));
+        let files: Vec<String> = session.swaps.iter().map(|x| x.file.clone()).collect();
+
+        u_input.opts.all_layers = true;
+        for file in files {
+            revert_file(&mut session, &file, &u_input.opts);
+            println!("Reverted '{file}'.");
+        }
+    }
+
+    return true;
+}
+
+fn cmd_export(u_input: &mut UserInput) -> bool {
+    if !u_input.opts.patch_export {
+        eprintln!("ERROR: Pick what to export, e.g. '--patch'.");
+        exit(1);
+    }
+
+    let session = Session::open(Path::new("."));
+    let files = picked_files(&session, u_input);
+
+    let out_file = u_input.opts.output.clone();
+    if let Some(out_file) = &out_file {
+        if tracked_file(&session, Path::new(out_file)) {
+            eprintln!("ERROR: '{out_file}' is part of the session, export to another file.");
+            exit(1);
+        }
+        if Path::new(out_file).exists() && !u_input.opts.noconfirm {
+            let confirmed = confirm_cmd(&format!("Overwrite '{out_file}'"));
+            if !confirmed {
+                return true;
+            }
+        }
+    }
+
+    let read = |path: &Path| -> Option<Vec<u8>> {
+        if !path.exists() {
+            return None;
+        }
+        return Some(fs::read(path).unwrap_or_else(|err| {
             eprintln!(
-                "ERROR: Couldn't get metadata from '{file}': {err}",
-                file = working_file.display()
+                "ERROR: Couldn't read '{file}': {err}",
+                file = path.display()
+            );
+            exit(1);
+        }));
+    };
+
+    let mut out = String::new();
+    for file in files {
+        let swap = session.swap(&file).unwrap_or_else(|| {
+            eprintln!("ERROR: '{file}' isn't swapped.");
+            exit(1);
+        });
+
+        // Toggled files have the original in place
+        let working_file = PathBuf::from(&file);
+        let saved_file = append_to_pathbuf(&working_file, FSWAP_EXT);
+        let (original, swapped) = if swap.toggled {
+            (read(&working_file), read(&saved_file))
+        } else {
+            (read(&saved_file), read(&working_file))
+        };
+
+        out.push_str(&file_patch(&file, original, swapped));
+    }
+
+    match out_file {
+        Some(out_file) => {
+            if let Err(err) = fs::write(&out_file, out) {
+                eprintln!("ERROR: Couldn't write to '{out_file}': {err}");
+                exit(1);
+            }
+        }
+        None => print!("{out}"),
+    }
+    return true;
+}
+
+// Whether path is the .fswap file, in the store, or a swapped file or its saved version
+fn tracked_file(session: &Session, path: &Path) -> bool {
+    // Only the parent is resolved, so paths that don't exist yet can be compared too
+    let absolute = |path: &Path| -> PathBuf {
+        let parent = match path.parent() {
+            Some(x) if !x.as_os_str().is_empty() => x,
+            _ => Path::new("."),
+        };
+        let parent = fs::canonicalize(parent).unwrap_or(parent.to_path_buf());
+        return parent.join(path.file_name().unwrap_or_default());
+    };
+
+    let path = absolute(path);
+    let dir = absolute(&session.dir.join(FSWAP_EXT))
+        .parent()
+        .unwrap_or(Path::new("."))
+        .to_path_buf();
+    if path == dir.join(FSWAP_EXT) || path.starts_with(dir.join(FSWAP_STORE)) {
+        return true;
+    }
+    return session.swaps.iter().any(|x| {
+        let file = session.dir.join(&x.file);
+        path == absolute(&file) || path == absolute(&append_to_pathbuf(&file, FSWAP_EXT))
+    });
+}
+
+// Unified diff of one file from a to b, git style with a/ and b/ prefixes so it applies with
+// patch -p1 or git apply. Missing sides are /dev/null
+fn file_patch(file: &str, a: Option<Vec<u8>>, b: Option<Vec<u8>>) -> String {
+    if a == b {
+        return String::new();
+    }
+
+    let name = |side: &Option<Vec<u8>>, prefix: &str| match side {
+        Some(_) => format!("{prefix}{file}"),
+        None => String::from("/dev/null"),
+    };
+    let (a_name, b_name) = (name(&a, "a/"), name(&b, "b/"));
+
+    let text = |side: Option<Vec<u8>>| String::from_utf8(side.unwrap_or_default()).ok();
+    let (a, b) = match (text(a), text(b)) {
+        (Some(a), Some(b)) => (a, b),
+        _ => return format!("Binary files {a_name} and {b_name} differ\n"),
+    };
+
+    return format!(
+        "--- {a_name}\n+++ {b_name}\n{hunks}",
+        hunks = diff::unified(&a, &b, 3)
+    );
+}
+
+fn cmd_bundle(u_input: &mut UserInput) -> bool {
+    let action = u_input.next_arg();
+    match action.as_str() {
+        "create" => bundle_create(u_input),
+        "apply" => bundle_apply(u_input),
+        _ => {
+            eprintln!("ERROR: Unknown bundle action '{action}'");
+            exit(1);
+        }
+    }
+
+    return true;
+}
+
+// A bundle is a tar archive with a BUNDLE_META file, listing the sources, swap records and the
+// hashes of the originals, and the versions of every swapped file under layers/N/FILE, N counting
+// from the bottom layer (1) to the top. A file can be stacked from the same source twice, so
+// versions can't be told apart by their source
+fn bundle_create(u_input: &mut UserInput) {
+    let out = PathBuf::from(u_input.next_arg());
+    let session = Session::open(Path::new("."));
+    let files = picked_files(&session, u_input);
+
+    if files.is_empty() {
+        eprintln!("No swapped files found.");
+        return;
+    }
+
+    let read = |path: &Path| -> Vec<u8> {
+        fs::read(path).unwrap_or_else(|err| {
+            eprintln!(
+                "ERROR: Couldn't read '{file}': {err}",
+                file = path.display()
             );
             exit(1);
+        })
+    };
+
+    let count = files.len();
+    let mut meta = String::new();
+    let mut sources: Vec<String> = vec![];
+    let mut entries: Vec<(String, Vec<u8>)> = vec![];
+    for file in files {
+        let swap = session.swap(&file).unwrap_or_else(|| {
+            eprintln!("ERROR: '{file}' isn't swapped.");
+            exit(1);
         });
 
-        if !working_md.file_type().is_file() {
+        let working_file = PathBuf::from(&file);
+        let saved_file = append_to_pathbuf(&working_file, FSWAP_EXT);
+        let (original, swapped) = if swap.toggled {
+            (working_file, saved_file)
+        } else {
+            (saved_file, working_file)
+        };
+
+        // The top layer is bundled with any edits made since the swap
+        let mut versions: Vec<(&str, PathBuf)> = vec![];
+        for (n, layer) in swap.under.iter().enumerate() {
+            versions.push((&layer.source, session.layer_path(&file, n + 1)));
+        }
+        versions.push((&swap.source, swapped));
+
+        for (n, (source, path)) in versions.into_iter().enumerate() {
+            if !sources.iter().any(|x| x == source) {
+                sources.push(source.to_string());
+            }
+            entries.push((format!("layers/{n}/{file}", n = n + 1), read(&path)));
+        }
+
+        meta.push_str(&format!(
+            "{record}\noriginal\t{file}\t{hash}\n",
+            record = swap.serialize(),
+            hash = hash_file(&original)
+        ));
+    }
+
+    for source in sources.iter().rev() {
+        meta.insert_str(0, &format!("source\t{source}\n"));
+    }
+    entries.insert(0, (String::from(BUNDLE_META), meta.into_bytes()));
+    archive::write_tar(&out, &entries);
+
+    println!(
+        "Bundled {count} swapped file(s) into '{file}'.",
+        file = out.display()
+    );
+}
+
+fn bundle_apply(u_input: &mut UserInput) {
+    let bundle = PathBuf::from(u_input.next_arg());
+    let entries = archive::read_tar_files(&bundle);
+
+    let meta = entries
+        .iter()
+        .find(|(x, _)| x == Path::new(BUNDLE_META))
+        .map(|(_, x)| String::from_utf8_lossy(x).to_string())
+        .unwrap_or_else(|| {
             eprintln!(
-                "ERROR: '{file}' isn't a normal file.",
-                file = working_file.display()
+                "ERROR: '{file}' isn't an fswap bundle.",
+                file = bundle.display()
             );
             exit(1);
+        });
+
+    let mut sources: Vec<String> = vec![];
+    let mut swaps: Vec<session::Swap> = vec![];
+    let mut originals: Vec<(String, String)> = vec![];
+    for line in meta.lines() {
+        let fields: Vec<&str> = line.split('\t').collect();
+        match fields.as_slice() {
+            ["source", name] if session::valid_source_name(name) => sources.push(name.to_string()),
+            ["swap", ..] => match session::Swap::parse(&fields) {
+                Ok(swap) => swaps.push(swap),
+                Err(field) => {
+                    eprintln!("ERROR: Unknown field '{field}' in the bundle.");
+                    exit(1);
+                }
+            },
+            ["original", file, hash] => originals.push((file.to_string(), hash.to_string())),
+            _ => {
+                eprintln!("ERROR: Malformed bundle line '{line}'.");
+                exit(1);
+            }
         }
+    }
 
-        if !source_file.exists() {
+    let new_session = !Path::new(FSWAP_EXT).exists();
+    let mut session = if new_session {
+        Session::new(Path::new("."))
+    } else {
+        Session::open(Path::new("."))
+    };
+
+    // Nothing is touched unless every file is there, as it was when the bundle was made
+    let mut mismatched = 0;
+    for (file, hash) in &originals {
+        let path = Path::new(file);
+        if session.swap(file).is_some() {
+            eprintln!("ERROR: '{file}' is already swapped, revert it first.");
+            mismatched += 1;
+        } else if !path.exists() {
+            eprintln!("ERROR: '{file}' doesn't exist.");
+            mismatched += 1;
+        } else if hash_file(path) != *hash {
+            eprintln!("ERROR: '{file}' differs from the original the bundle was made from.");
+            mismatched += 1;
+        }
+    }
+    if mismatched > 0 && !u_input.opts.force {
+        eprintln!("{mismatched} file(s) don't match, use --force to apply the bundle anyway.");
+        exit(1);
+    }
+
+    // Bundles can come from anywhere, every swap has to stay in the fswap directory, have its
+    // original checked above, and come with all of its layers
+    let plain_path = |file: &str| {
+        !file.is_empty()
+            && Path::new(file)
+                .components()
+                .all(|x| matches!(x, std::path::Component::Normal(_)))
+    };
+    let mut layers: Vec<Vec<(&str, &Vec<u8>)>> = vec![];
+    for swap in &swaps {
+        if !plain_path(&swap.file) {
+            eprintln!(
+                "ERROR: Bundle swaps in '{file}', which is outside of the fswap directory.",
+                file = swap.file
+            );
+            exit(1);
+        }
+        if !originals.iter().any(|(file, _)| *file == swap.file) {
             eprintln!(
-                "ERROR: '{file}' does not exist.",
-                file = source_file.display()
+                "ERROR: Bundle swaps in '{file}' without the hash of its original.",
+                file = swap.file
             );
             exit(1);
         }
 
-        let swapped_file = append_to_pathbuf(&working_file, FSWAP_EXT);
+        let mut versions: Vec<(&str, &Vec<u8>)> = vec![];
+        let stack = swap.under.iter().map(|x| &x.source).chain([&swap.source]);
+        for (n, source) in stack.enumerate() {
+            if !sources.contains(source) {
+                eprintln!("ERROR: Bundle uses source '{source}' without listing it.");
+                exit(1);
+            }
+            let path = Path::new("layers")
+                .join((n + 1).to_string())
+                .join(&swap.file);
+            let contents = entries.iter().find(|(x, _)| *x == path).unwrap_or_else(|| {
+                eprintln!("ERROR: Bundle is missing '{file}'.", file = path.display());
+                exit(1);
+            });
+            versions.push((source, &contents.1));
+        }
+        layers.push(versions);
+    }
+
+    // The sources are named after the bundle, so they don't clash with the session's own
+    let stem = bundle
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .split('.')
+        .next()
+        .unwrap_or_default()
+        .to_string();
+    let dir = Path::new(FSWAP_STORE).join("bundles").join(&stem);
+    let local_name = |name: &str| source_name(&format!("{stem}-{name}"));
+    for name in &sources {
+        let local = local_name(name);
+        if session
+            .source(&local)
+            .is_some_and(|x| x.path != dir.join(name).display().to_string())
+        {
+            eprintln!("ERROR: Source '{local}' already exists, rename the bundle to apply it.");
+            exit(1);
+        }
+    }
+
+    for name in &sources {
+        if let Err(err) = fs::create_dir_all(dir.join(name)) {
+            eprintln!(
+                "ERROR: Couldn't create '{dir}': {err}",
+                dir = dir.join(name).display()
+            );
+            exit(1);
+        }
+        let local = local_name(name);
+        if session.source(&local).is_none() {
+            session.sources.push(Source {
+                name: local,
+                path: dir.join(name).display().to_string(),
+                rev: None,
+                template: false,
+            });
+        }
+    }
+    session.save();
+    if new_session {
+        registry::add(Path::new("."));
+    }
+
+    u_input.opts.partial = false;
+    u_input.opts.rebackup = false;
+    let mut swapped = 0;
+    for (swap, versions) in swaps.iter().zip(layers) {
+        if session.swap(&swap.file).is_some() {
+            continue;
+        }
+        swapped += 1;
+
+        // Each version is put in its source right before it's swapped in, so a source used by
+        // more than one layer ends up with the top one
+        for (source, contents) in versions {
+            let dest = dir.join(source).join(&swap.file);
+            let result = dest
+                .parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .and_then(|_| fs::write(&dest, contents));
+            if let Err(err) = result {
+                eprintln!(
+                    "ERROR: Couldn't write to '{file}': {err}",
+                    file = dest.display()
+                );
+                exit(1);
+            }
+            swap_file(&mut session, &local_name(source), &swap.file, &u_input.opts);
+        }
+
+        if swap.toggled {
+            flip_toggle(&mut session, &swap.file);
+            session.save();
+        }
+    }
+
+    println!(
+        "Applied bundle '{file}', swapped {swapped} file(s).",
+        file = bundle.display()
+    );
+}
+
+fn cmd_relink(u_input: &mut UserInput) -> bool {
+    let arg = u_input.next_arg();
+    let new_dir = Path::new(&arg);
+
+    let mut session = Session::open(Path::new("."));
+    let picked = session.pick_source(u_input.opts.from.as_deref());
+    let name = picked.name.clone();
+    // Revisions are read from the repository the fswap directory is in, wherever it moved
+    if let Some(rev) = &picked.rev {
+        eprintln!("ERROR: Source '{name}' is git revision '{rev}', it can't be relinked.");
+        exit(1);
+    }
+
+    let path = link_source(&arg, Path::new("."), &u_input.opts);
+    let relinked = Source {
+        name: name.clone(),
+        path,
+        rev: None,
+        template: u_input.opts.template,
+    };
+
+    // Every version swapped in from the source, with the hash it had then
+    let mut expected: Vec<(String, Option<String>)> = vec![];
+    for swap in &session.swaps {
+        if swap.source == name {
+            expected.push((swap.file.clone(), swap.hash.clone()));
+        }
+        for layer in swap.under.iter().filter(|x| x.source == name) {
+            expected.push((swap.file.clone(), layer.hash.clone()));
+        }
+    }
+
+    let mut problems = 0;
+    for (file, hash) in &expected {
+        let source_file = session.source_file(&relinked, file);
+        if !source_file.exists() {
+            eprintln!(
+                "ERROR: '{file}' is missing from '{dir}'.",
+                dir = new_dir.display()
+            );
+            problems += 1;
+        } else if u_input.opts.verify
+            && let Some(hash) = hash
+            && hash_file(&source_file) != *hash
+        {
+            eprintln!(
+                "ERROR: '{file}' in '{dir}' differs from the version that was swapped in.",
+                dir = new_dir.display()
+            );
+            problems += 1;
+        }
+    }
+    if problems > 0 && !u_input.opts.force {
+        eprintln!("{problems} file(s) don't match, use --force to relink anyway.");
+        exit(1);
+    }
+
+    let source = session
+        .sources
+        .iter_mut()
+        .find(|x| x.name == name)
+        .expect("picked sources exist");
+    let old = source.path.clone();
+    let new = relinked.path.clone();
+    *source = relinked;
+    session.save();
+
+    println!(
+        "Relinked source '{name}' to '{path}', checked {n} file(s).",
+        path = new_dir.display(),
+        n = expected.len()
+    );
+    if u_input.opts.verbose {
+        println!("INFO: Changed the path of source '{name}' from '{old}' to '{new}'.");
+    }
+
+    return true;
+}
+
+fn cmd_undo(u_input: &mut UserInput) -> bool {
+    let mut session = Session::open(Path::new("."));
+    let files = match undo::undo(&mut session) {
+        Some(x) => x,
+        None => {
+            println!("Nothing to undo.");
+            return true;
+        }
+    };
+
+    for (file, before) in files {
+        let path = Path::new(&file);
+        let after = path.exists().then(|| hash_file(path));
+        session.log("undo", &file, before.as_deref(), after.as_deref());
 
-        if !u_input.opts.noconfirm && swapped_file.exists() {
-            let confirmed = confirm_cmd(&format!(
-                "'{file}' already exists, overwrite this file",
-                file = swapped_file.display()
-            ));
-            if !confirmed {
-                continue;
-            }
+        if u_input.opts.verbose {
+            println!("INFO: Restored '{file}'.");
         }
+    }
+    println!("Undid the last change.");
 
-        if let Err(err) = fs::rename(&working_file, &swapped_file) {
-            eprintln!(
-                "ERROR: Couldn't rename '{src}' to '{dest}': {err}",
-                src = working_file.display(),
-                dest = swapped_file.display()
-            );
-            exit(1);
-        }
+    return true;
+}
 
-        if let Err(err) = fs::copy(&source_file, &working_file) {
-            eprintln!(
-                "ERROR: Couldn't copy '{src}' to '{dest}': {err}",
-                src = source_file.display(),
-                dest = working_file.display()
-            );
-            exit(1);
+fn cmd_redo(u_input: &mut UserInput) -> bool {
+    let mut session = Session::open(Path::new("."));
+    let files = match undo::redo(&mut session) {
+        Some(x) => x,
+        None => {
+            println!("Nothing to redo.");
+            return true;
         }
+    };
+
+    for (file, before) in files {
+        let path = Path::new(&file);
+        let after = path.exists().then(|| hash_file(path));
+        session.log("redo", &file, before.as_deref(), after.as_deref());
 
         if u_input.opts.verbose {
-            println!(
-                "INFO: Renamed '{src}' -> '{dest}'.",
-                src = working_file.display(),
-                dest = swapped_file.display()
-            );
-            println!(
-                "INFO: Copied '{src}' -> '{dest}'.",
-                src = source_file.display(),
-                dest = working_file.display()
-            );
+            println!("INFO: Restored '{file}'.");
         }
     }
+    println!("Redid the last undone change.");
 
     return true;
 }
 
-fn cmd_revert(u_input: &mut UserInput) -> bool {
-    if let Err(err) = File::open(FSWAP_EXT) {
-        eprintln!("ERROR: Couldn't open '{FSWAP_EXT}': {err}");
+// Checkpoints are files in the store named after them, so names can't point anywhere else
+fn checkpoint_name(u_input: &mut UserInput) -> String {
+    let name = u_input.next_arg();
+    if !session::valid_source_name(&name) || name.starts_with('.') {
+        eprintln!("ERROR: Invalid checkpoint name '{name}'.");
         exit(1);
     }
+    return name;
+}
 
-    let files: Vec<String>;
-    if u_input.opts.all {
-        let swapped_files = match find_files_with(&PathBuf::from("."), Some(FSWAP_EXT)) {
-            Some(x) => x,
-            None => {
-                eprintln!("No fswap files found.");
-                return true;
-            }
-        };
+fn cmd_checkpoint(u_input: &mut UserInput) -> bool {
+    let action = if u_input.argc > 0 {
+        u_input.next_arg()
+    } else {
+        String::from("list")
+    };
 
-        files = swapped_files
-            .iter()
-            .map(|x| {
-                x.strip_suffix(FSWAP_EXT)
-                    .expect("Files that contain .fswap should always have it as a suffix")
-                    .to_string()
-            })
-            .filter(|x| !x.eq("./"))
-            .collect();
-    } else if u_input.opts.recursive {
-        let dirs = &u_input.args;
-        let mut out_files: Vec<String> = vec![];
-        for dir in dirs {
-            let swapped_files = match find_files_with(&PathBuf::from(&dir), Some(FSWAP_EXT)) {
-                Some(x) => x,
-                None => {
-                    eprintln!("No fswap files found.");
+    let mut session = Session::open(Path::new("."));
+    let dir = session.dir.join(FSWAP_STORE).join("checkpoints");
+
+    match action.as_str() {
+        "save" => {
+            let name = checkpoint_name(u_input);
+
+            let path = dir.join(&name);
+            if path.exists() && !u_input.opts.noconfirm {
+                let confirmed = confirm_cmd(&format!("Overwrite checkpoint '{name}'"));
+                if !confirmed {
                     return true;
                 }
-            };
+            }
 
-            let cur_files = swapped_files
-                .iter()
-                .map(|x| {
-                    x.strip_suffix(FSWAP_EXT)
-                        .expect("Files that contain .fswap should always have it as a suffix")
-                        .to_string()
-                })
-                .filter(|x| !x.eq("./"))
-                .collect();
-            out_files = combine_string_vecs(&out_files, &cur_files);
+            // Each swap record is followed by the hash of the swapped version, which is kept in
+            // the store in case the source changes
+            let mut out = String::new();
+            for swap in &session.swaps {
+                let swapped_file = if swap.toggled {
+                    append_to_pathbuf(Path::new(&swap.file), FSWAP_EXT)
+                } else {
+                    PathBuf::from(&swap.file)
+                };
+                let hash = session.store_object(&swapped_file).unwrap_or_else(|| {
+                    eprintln!(
+                        "ERROR: '{file}' doesn't exist.",
+                        file = swapped_file.display()
+                    );
+                    exit(1);
+                });
+                out.push_str(&format!(
+                    "{record}\nfile\t{file}\t{hash}\n",
+                    record = swap.serialize(),
+                    file = swap.file
+                ));
+            }
+
+            let result = fs::create_dir_all(&dir).and_then(|_| fs::write(&path, out));
+            if let Err(err) = result {
+                eprintln!(
+                    "ERROR: Couldn't write to '{file}': {err}",
+                    file = path.display()
+                );
+                exit(1);
+            }
+
+            println!(
+                "Saved checkpoint '{name}' with {n} swapped file(s).",
+                n = session.swaps.len()
+            );
         }
+        "restore" => {
+            let name = checkpoint_name(u_input);
+            let path = dir.join(&name);
+            if !path.exists() {
+                eprintln!("ERROR: Unknown checkpoint '{name}'.");
+                exit(1);
+            }
 
-        files = out_files;
-    } else {
-        files = u_input.args.clone();
-    }
+            let contents = fs::read_to_string(&path).unwrap_or_else(|err| {
+                eprintln!(
+                    "ERROR: Couldn't read '{file}': {err}",
+                    file = path.display()
+                );
+                exit(1);
+            });
 
-    let mut files = files.iter();
-    loop {
-        let file = match files.next() {
-            Some(x) => x,
-            None => break,
-        };
+            let mut saved: Vec<(session::Swap, String)> = vec![];
+            let lines: Vec<&str> = contents.lines().collect();
+            for pair in lines.chunks(2) {
+                let record: Vec<&str> = pair[0].split('\t').collect();
+                let file: Vec<&str> = pair.get(1).map_or(vec![], |x| x.split('\t').collect());
+                match (session::Swap::parse(&record), file.as_slice()) {
+                    (Ok(swap), ["file", _, hash]) => saved.push((swap, hash.to_string())),
+                    _ => {
+                        eprintln!("ERROR: '{file}' is malformed.", file = path.display());
+                        exit(1);
+                    }
+                }
+            }
+
+            // Checkpoints go back to exactly the saved swaps, never layer by layer
+            u_input.opts.all_layers = true;
+            u_input.opts.partial = false;
+            u_input.opts.rebackup = false;
+
+            let files: Vec<String> = session.swaps.iter().map(|x| x.file.clone()).collect();
+            for file in files {
+                if !saved.iter().any(|(x, _)| x.file == file) {
+                    revert_file(&mut session, &file, &u_input.opts);
+                    if u_input.opts.verbose && session.swap(&file).is_none() {
+                        println!("INFO: Reverted '{file}'.");
+                    }
+                }
+            }
+
+            for (swap, hash) in saved {
+                restore_swap(&mut session, swap, &hash, &u_input.opts);
+            }
+
+            println!("Restored checkpoint '{name}'.");
+        }
+        "list" => {
+            let mut names: Vec<String> = match fs::read_dir(&dir) {
+                Ok(entries) => entries
+                    .flatten()
+                    .map(|x| x.file_name().to_string_lossy().to_string())
+                    .collect(),
+                Err(_) => vec![],
+            };
+            names.sort();
 
-        // Misleading name, in this case source_file actually refers to the file that was swapped
-        // in FROM the source directory, not a file in the source directory
-        let source_file = PathBuf::from(&file);
+            for name in names {
+                let swapped = fs::read_to_string(dir.join(&name))
+                    .map_or(0, |x| x.lines().filter(|x| x.starts_with("swap\t")).count());
+                println!("  {name}\t({swapped} swapped)");
+            }
+        }
+        "remove" => {
+            let name = checkpoint_name(u_input);
+            let path = dir.join(&name);
+            if !path.exists() {
+                eprintln!("ERROR: Unknown checkpoint '{name}'.");
+                exit(1);
+            }
 
-        if source_file.exists() {
-            if let Err(err) = fs::remove_file(&source_file) {
+            if let Err(err) = fs::remove_file(&path) {
                 eprintln!(
                     "ERROR: couldn't remove '{file}': {err}",
-                    file = source_file.display()
+                    file = path.display()
                 );
                 exit(1);
             }
-        }
 
-        let working_file = append_to_pathbuf(&source_file, FSWAP_EXT);
-        if !working_file.exists() {
-            eprintln!(
-                "ERROR: '{file}' doesn't exist.",
-                file = working_file.display()
-            );
+            if u_input.opts.verbose {
+                println!("INFO: Removed checkpoint '{name}'.");
+            }
+        }
+        _ => {
+            eprintln!("ERROR: Unknown checkpoint action '{action}'");
             exit(1);
         }
+    }
+
+    return true;
+}
+
+// Brings file back to a swap saved in a checkpoint, swapping its layers in again from their
+// sources. hash is the swapped version at the time, which is put back if the file differs
+fn restore_swap(session: &mut Session, saved: session::Swap, hash: &str, opts: &Opts) {
+    let file = saved.file.clone();
+    let working_file = PathBuf::from(&file);
+
+    if let Some(swap) = session.swap(&file) {
+        let swapped_file = if swap.toggled {
+            append_to_pathbuf(&working_file, FSWAP_EXT)
+        } else {
+            working_file.clone()
+        };
+        if swap.serialize() == saved.serialize()
+            && swapped_file.exists()
+            && hash_file(&swapped_file) == hash
+        {
+            return;
+        }
 
-        if let Err(err) = fs::rename(&working_file, &source_file) {
+        revert_file(session, &file, opts);
+        if session.swap(&file).is_some() {
+            return;
+        }
+    }
+
+    for layer in &saved.under {
+        swap_file(session, &layer.source, &file, opts);
+    }
+    swap_file(session, &saved.source, &file, opts);
+
+    let before = hash_file(&working_file);
+    if before != hash {
+        println!("'{file}' differs from the checkpoint, putting back the saved version.");
+        if let Err(err) = fs::write(&working_file, session.object(hash)) {
             eprintln!(
-                "ERROR: Couldn't rename '{work_file}' to '{src_file}': {err}",
-                work_file = working_file.display(),
-                src_file = source_file.display()
+                "ERROR: Couldn't write to '{file}': {err}",
+                file = working_file.display()
             );
             exit(1);
         }
+        session.log("checkpoint", &file, Some(&before), Some(hash));
+    }
 
-        if u_input.opts.verbose {
-            println!("INFO: Removed '{file}'.", file = source_file.display());
-            println!(
-                "INFO: Renamed '{src}' -> '{dest}'.",
-                src = working_file.display(),
-                dest = source_file.display()
-            );
-        }
+    let toggled = saved.toggled;
+    match session.swap_mut(&file) {
+        Some(x) => *x = saved,
+        None => session.swaps.push(saved),
+    }
+    if let Some(swap) = session.swap_mut(&file) {
+        swap.toggled = false;
+    }
+    if toggled {
+        flip_toggle(session, &file);
     }
+    session.save();
 
-    return true;
+    if opts.verbose {
+        println!("INFO: Restored '{file}'.");
+    }
+}
+
+// FILES given as arguments, or every swapped file with -a or if no FILES are given
+fn picked_files(session: &Session, u_input: &UserInput) -> Vec<String> {
+    if u_input.opts.all || u_input.argc == 0 {
+        return session.swaps.iter().map(|x| x.file.clone()).collect();
+    }
+    return u_input.args.iter().map(|x| session::normalize(x)).collect();
 }
 
-fn combine_string_vecs(a: &Vec<String>, b: &Vec<String>) -> Vec<String> {
-    let mut ret = a.clone();
+fn combine_string_vecs(a: &[String], b: &[String]) -> Vec<String> {
+    let mut ret = a.to_vec();
     b.iter().for_each(|x| ret.push(x.to_string()));
     return ret;
 }
 
+// sha256 of the file's contents, as hex
+fn hash_file(path: &Path) -> String {
+    let contents = fs::read(path).unwrap_or_else(|err| {
+        eprintln!(
+            "ERROR: Couldn't read '{file}': {err}",
+            file = path.display()
+        );
+        exit(1);
+    });
+
+    return hash_bytes(&contents);
+}
+
+fn hash_bytes(contents: &[u8]) -> String {
+    return Sha256::digest(contents)
+        .iter()
+        .map(|x| format!("{x:02x}"))
+        .collect();
+}
+
+// Local time as YYYY-MM-DD HH:MM:SS
+fn format_time(secs: u64) -> String {
+    let time = secs as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+        return secs.to_string();
+    }
+
+    return format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min,
+        tm.tm_sec
+    );
+}
+
+// Rough duration, in the units parse_time accepts
+fn format_age(secs: u64) -> String {
+    return match secs {
+        0..60 => format!("{secs}s"),
+        60..3600 => format!("{m}m", m = secs / 60),
+        3600..86400 => format!("{h}h", h = secs / 3600),
+        _ => format!("{d}d", d = secs / 86400),
+    };
+}
+
+// Accepts how long ago (30m, 2h, 7d, ...), a local date and time (YYYY-MM-DD [HH:MM[:SS]]), or
+// seconds since the epoch (@1700000000)
+fn parse_time(arg: &str) -> u64 {
+    let invalid = || -> ! {
+        eprintln!("ERROR: Invalid time '{arg}'");
+        exit(1);
+    };
+
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |x| x.as_secs());
+
+    if let Some(secs) = arg.strip_prefix('@') {
+        return secs.parse().unwrap_or_else(|_| invalid());
+    }
+
+    if let Some(unit) = arg.chars().last().filter(|x| x.is_ascii_alphabetic()) {
+        let n: u64 = arg[..arg.len() - 1].parse().unwrap_or_else(|_| invalid());
+        let secs = match unit {
+            's' => n,
+            'm' => n * 60,
+            'h' => n * 60 * 60,
+            'd' => n * 60 * 60 * 24,
+            'w' => n * 60 * 60 * 24 * 7,
+            _ => invalid(),
+        };
+        return now.saturating_sub(secs);
+    }
+
+    let (date, time) = match arg.split_once([' ', 'T']) {
+        Some((date, time)) => (date, time),
+        None => (arg, "00:00:00"),
+    };
+    let date: Vec<i32> = date
+        .split('-')
+        .map(|x| x.parse().unwrap_or_else(|_| invalid()))
+        .collect();
+    let time: Vec<i32> = time
+        .split(':')
+        .map(|x| x.parse().unwrap_or_else(|_| invalid()))
+        .collect();
+
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    match (date.as_slice(), time.as_slice()) {
+        ([year, month, day], [hour, min, rest @ ..]) if rest.len() <= 1 => {
+            tm.tm_year = year - 1900;
+            tm.tm_mon = month - 1;
+            tm.tm_mday = *day;
+            tm.tm_hour = *hour;
+            tm.tm_min = *min;
+            tm.tm_sec = rest.first().copied().unwrap_or(0);
+            tm.tm_isdst = -1;
+        }
+        _ => invalid(),
+    }
+
+    let secs = unsafe { libc::mktime(&mut tm) };
+    if secs < 0 {
+        invalid();
+    }
+    return secs as u64;
+}
+
 // https://internals.rust-lang.org/t/pathbuf-has-set-extension-but-no-add-extension-cannot-cleanly-turn-tar-to-tar-gz/14187/11
-fn append_to_pathbuf(pb: &PathBuf, ext: &str) -> PathBuf {
-    let mut path: OsString = pb.clone().into();
+fn append_to_pathbuf(pb: &Path, ext: &str) -> PathBuf {
+    let mut path: OsString = pb.to_path_buf().into();
     path.push(ext);
     path.into()
 }
 
 // wrapper for string.strip_suffix()
-fn _strip_suffix_from_pathbuf(pb: &PathBuf, ext: &str) -> Option<PathBuf> {
-    let path = String::from(pb.clone().into_os_string().into_string().unwrap());
+fn _strip_suffix_from_pathbuf(pb: &Path, ext: &str) -> Option<PathBuf> {
+    let path = pb.to_path_buf().into_os_string().into_string().unwrap();
     match path.strip_suffix(ext) {
         Some(x) => {
             return Some(PathBuf::from(x));
@@ -615,21 +3150,68 @@ fn _strip_suffix_from_pathbuf(pb: &PathBuf, ext: &str) -> Option<PathBuf> {
 }
 
 struct Opts {
+    absolute: bool,
     all: bool,
+    all_layers: bool,
+    discard: bool,
+    force: bool,
+    from: Option<String>,
+    from_rev: Option<String>,
+    git: Option<String>,
     help: bool,
+    keep_swapped: bool,
+    merge: bool,
     noconfirm: bool,
+    output: Option<String>,
+    partial: bool,
+    patch: Option<String>,
+    patch_export: bool,
+    rebackup: bool,
     recursive: bool,
+    revert_on_exit: bool,
+    session: Option<String>,
+    since: Option<String>,
+    snapshot: bool,
+    swap_new: bool,
+    template: bool,
+    trash: bool,
+    until: Option<String>,
     verbose: bool,
+    verify: bool,
 }
 
 impl Opts {
     fn new() -> Self {
+        let config = config::load();
         Self {
+            absolute: false,
             all: false,
+            all_layers: false,
+            discard: false,
+            force: false,
+            from: None,
+            from_rev: None,
+            git: None,
             help: false,
+            keep_swapped: false,
+            merge: false,
             noconfirm: false,
+            output: None,
+            partial: false,
+            patch: None,
+            patch_export: false,
+            rebackup: false,
             recursive: false,
+            revert_on_exit: false,
+            session: None,
+            since: None,
+            snapshot: false,
+            swap_new: false,
+            template: false,
+            trash: config.trash,
+            until: None,
             verbose: false,
+            verify: false,
         }
     }
 }
@@ -645,8 +3227,7 @@ impl UserInput {
     fn new() -> Self {
         let mut args_in: Vec<String> = env::args().collect();
         args_in.remove(0);
-        let opts_out = Self::args_to_flags(&args_in);
-        let args_out = Self::strip_opts_from_args(&args_in);
+        let (args_out, opts_out) = Self::parse_args(args_in);
         let argc_out = args_out.len();
 
         return Self {
@@ -670,61 +3251,110 @@ impl UserInput {
         println!("Usage: fswap [COMMAND] [OPTIONS...] [ARGUMENTS...]\nSee 'fswap help' for more information.");
     }
 
-    fn strip_opts_from_args(args: &Vec<String>) -> Vec<String> {
-        // IMPORTANT NOTE: '⟡' looks like a face
-        return args
-            .clone()
-            .into_iter()
-            .filter(|x| x.chars().next().unwrap_or('⟡') != '-')
-            .collect();
-    }
-
-    fn args_to_flags(args: &Vec<String>) -> Opts {
+    // Splits args into options and everything else, options that take a value consume the arg
+    // after them (or the part after '=', e.g. --from=name)
+    fn parse_args(args: Vec<String>) -> (Vec<String>, Opts) {
         let mut opts = Opts::new();
-        for arg in args {
+        let mut rest: Vec<String> = vec![];
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
             let mut chars = arg.chars().peekable();
-            match chars.next() {
-                Some(c) if c == '-' => c,
-                _ => continue,
-            };
+            if chars.next() != Some('-') {
+                rest.push(arg);
+                continue;
+            }
 
             // peek here so it wont consume an important character valid in case of short opt
-            let long_opt = match chars.peek() {
-                Some(c) if *c == '-' => true,
-                _ => false,
-            };
+            let long_opt = chars.peek() == Some(&'-');
 
             if !long_opt {
-                loop {
-                    match chars.next() {
-                        Some(c) if c == 'a' => opts.all = true,
-                        Some(c) if c == 'h' => opts.help = true,
-                        Some(c) if c == 'n' => opts.noconfirm = true,
-                        Some(c) if c == 'r' => opts.recursive = true,
-                        Some(c) if c == 'v' => opts.verbose = true,
-                        Some(_) => {
+                while let Some(c) = chars.next() {
+                    match c {
+                        'a' => opts.all = true,
+                        'h' => opts.help = true,
+                        'n' => opts.noconfirm = true,
+                        // -o takes the rest of the arg as its value, or the next arg, like -oFILE
+                        'o' => {
+                            let rest: String = chars.by_ref().collect();
+                            opts.output = if rest.is_empty() {
+                                args.next()
+                            } else {
+                                Some(rest)
+                            };
+                            if opts.output.is_none() {
+                                eprintln!("ERROR: Option '-o' requires a value");
+                                exit(1);
+                            }
+                        }
+                        'p' => opts.partial = true,
+                        'r' => opts.recursive = true,
+                        'v' => opts.verbose = true,
+                        _ => {
                             eprintln!("ERROR: Invalid options '{arg}'");
                             exit(1);
                         }
-                        None => break,
                     };
                 }
-            } else {
-                match arg.as_str() {
-                    "--all" => opts.all = true,
-                    "--help" => opts.help = true,
-                    "--noconfirm" => opts.noconfirm = true,
-                    "--recursive" => opts.recursive = true,
-                    "--verbose" => opts.verbose = true,
-                    _ => {
-                        eprintln!("ERROR: Invalid options '{arg}'");
+                continue;
+            }
+
+            let (name, inline_value) = match arg.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (arg.as_str(), None),
+            };
+
+            let mut value = || -> String {
+                match inline_value.clone().or_else(|| args.next()) {
+                    Some(x) => x,
+                    None => {
+                        eprintln!("ERROR: Option '{name}' requires a value");
                         exit(1);
                     }
-                };
-            }
+                }
+            };
+
+            match name {
+                "--absolute" => opts.absolute = true,
+                "--all" => opts.all = true,
+                "--all-layers" => opts.all_layers = true,
+                "--force" => opts.force = true,
+                "--discard" => opts.discard = true,
+                "--from" => opts.from = Some(value()),
+                "--from-rev" => opts.from_rev = Some(value()),
+                "--git" => opts.git = Some(value()),
+                "--help" => opts.help = true,
+                "--keep-swapped" => opts.keep_swapped = true,
+                "--merge" => opts.merge = true,
+                "--no-trash" => opts.trash = false,
+                "--noconfirm" => opts.noconfirm = true,
+                "--output" => opts.output = Some(value()),
+                "--partial" => opts.partial = true,
+                // export --patch picks what to export, swap --patch FILE takes the patch to swap in
+                "--patch" if rest.first().is_some_and(|x| x == "export" || x == "ex") => {
+                    opts.patch_export = true
+                }
+                "--patch" => opts.patch = Some(value()),
+                "--rebackup" => opts.rebackup = true,
+                "--recursive" => opts.recursive = true,
+                "--revert-on-exit" => opts.revert_on_exit = true,
+                "--session" => opts.session = Some(value()),
+                "--since" => opts.since = Some(value()),
+                "--snapshot" => opts.snapshot = true,
+                "--swap-new" => opts.swap_new = true,
+                "--template" => opts.template = true,
+                "--trash" => opts.trash = true,
+                "--until" => opts.until = Some(value()),
+                "--verbose" => opts.verbose = true,
+                "--verify" => opts.verify = true,
+                _ => {
+                    eprintln!("ERROR: Invalid options '{arg}'");
+                    exit(1);
+                }
+            };
         }
 
-        return opts;
+        return (rest, opts);
     }
 }
 
@@ -736,17 +3366,37 @@ struct Command {
 
 // code style inspired by https://github.com/rexim/tore
 // tool usage inspired by nmcli
-const COMMANDS: [Command; 6] = [
+const COMMANDS: [Command; 20] = [
+    Command {
+        name: "accept",
+        short: "a",
+        func: cmd_accept,
+    },
     Command {
         name: "begin",
         short: "b",
         func: cmd_begin,
     },
+    Command {
+        name: "bundle",
+        short: "bu",
+        func: cmd_bundle,
+    },
+    Command {
+        name: "checkpoint",
+        short: "c",
+        func: cmd_checkpoint,
+    },
     Command {
         name: "end",
         short: "e",
         func: cmd_end,
     },
+    Command {
+        name: "export",
+        short: "ex",
+        func: cmd_export,
+    },
     Command {
         name: "help",
         short: "h",
@@ -757,16 +3407,66 @@ const COMMANDS: [Command; 6] = [
         short: "i",
         func: cmd_info,
     },
+    Command {
+        name: "log",
+        short: "l",
+        func: cmd_log,
+    },
+    Command {
+        name: "push",
+        short: "p",
+        func: cmd_push,
+    },
+    Command {
+        name: "redo",
+        short: "red",
+        func: cmd_redo,
+    },
+    Command {
+        name: "refresh",
+        short: "re",
+        func: cmd_refresh,
+    },
+    Command {
+        name: "relink",
+        short: "rel",
+        func: cmd_relink,
+    },
     Command {
         name: "revert",
         short: "r",
         func: cmd_revert,
     },
+    Command {
+        name: "sessions",
+        short: "se",
+        func: cmd_sessions,
+    },
+    Command {
+        name: "source",
+        short: "so",
+        func: cmd_source,
+    },
     Command {
         name: "swap",
         short: "s",
         func: cmd_swap,
     },
+    Command {
+        name: "toggle",
+        short: "t",
+        func: cmd_toggle,
+    },
+    Command {
+        name: "undo",
+        short: "u",
+        func: cmd_undo,
+    },
+    Command {
+        name: "watch",
+        short: "w",
+        func: cmd_watch,
+    },
 ];
 
 // Commands either succeed and return, or exit the program with non-zero exit code
@@ -778,6 +3478,15 @@ fn main() {
         exit(0);
     }
 
+    // Run everything from the session's directory, as if fswap was started there
+    if let Some(dir) = &u_input.opts.session {
+        check_dir(Path::new(dir));
+        if let Err(err) = env::set_current_dir(dir) {
+            eprintln!("ERROR: Couldn't change to '{dir}': {err}");
+            exit(1);
+        }
+    }
+
     let mut command = String::from("info");
     if u_input.argc > 0 {
         command = u_input.next_arg();
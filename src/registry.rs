@@ -0,0 +1,116 @@
+// Keeps track of every session begun on this machine, so they can be listed from anywhere. The
+// registry is a tab separated file, $XDG_STATE_HOME/fswap/sessions (~/.local/state/fswap/sessions
+// by default), one line per session:
+//
+//   TIME  DIR               the session in DIR (absolute) was begun at TIME, seconds since the epoch
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::FSWAP_EXT;
+
+pub struct Entry {
+    pub time: u64,
+    pub dir: PathBuf,
+}
+
+// None if neither $XDG_STATE_HOME nor $HOME is set, then nothing is registered
+fn registry_path() -> Option<PathBuf> {
+    let state = match env::var_os("XDG_STATE_HOME").filter(|x| !x.is_empty()) {
+        Some(x) => PathBuf::from(x),
+        None => PathBuf::from(env::var_os("HOME")?).join(".local/state"),
+    };
+    return Some(state.join("fswap").join("sessions"));
+}
+
+pub fn entries() -> Vec<Entry> {
+    let path = match registry_path() {
+        Some(x) if x.exists() => x,
+        _ => return vec![],
+    };
+
+    let contents = fs::read_to_string(&path).unwrap_or_else(|err| {
+        eprintln!(
+            "ERROR: Couldn't read '{file}': {err}",
+            file = path.display()
+        );
+        exit(1);
+    });
+
+    let mut entries: Vec<Entry> = vec![];
+    for line in contents.lines() {
+        if let Some((time, dir)) = line.split_once('\t') {
+            entries.push(Entry {
+                time: time.parse().unwrap_or(0),
+                dir: PathBuf::from(dir),
+            });
+        }
+    }
+    return entries;
+}
+
+fn write_entries(entries: &[Entry]) {
+    let path = match registry_path() {
+        Some(x) => x,
+        None => return,
+    };
+
+    let contents: String = entries
+        .iter()
+        .map(|x| format!("{time}\t{dir}\n", time = x.time, dir = x.dir.display()))
+        .collect();
+
+    if let Some(dir) = path.parent()
+        && let Err(err) = fs::create_dir_all(dir)
+    {
+        eprintln!("ERROR: Couldn't create '{dir}': {err}", dir = dir.display());
+        exit(1);
+    }
+    if let Err(err) = fs::write(&path, contents) {
+        eprintln!(
+            "ERROR: Couldn't write to '{file}': {err}",
+            file = path.display()
+        );
+        exit(1);
+    }
+}
+
+fn absolute(dir: &Path) -> PathBuf {
+    return fs::canonicalize(dir).unwrap_or(dir.to_path_buf());
+}
+
+pub fn add(dir: &Path) {
+    let dir = absolute(dir);
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |x| x.as_secs());
+
+    let mut entries = entries();
+    entries.retain(|x| x.dir != dir);
+    entries.push(Entry { time, dir });
+    write_entries(&entries);
+}
+
+pub fn remove(dir: &Path) {
+    let dir = absolute(dir);
+    let mut entries = entries();
+    let count = entries.len();
+    entries.retain(|x| x.dir != dir);
+    if entries.len() != count {
+        write_entries(&entries);
+    }
+}
+
+// Drops the sessions whose .fswap file is gone, returns their directories
+pub fn prune() -> Vec<PathBuf> {
+    let (kept, gone): (Vec<Entry>, Vec<Entry>) = entries()
+        .into_iter()
+        .partition(|x| x.dir.join(FSWAP_EXT).exists());
+    if !gone.is_empty() {
+        write_entries(&kept);
+    }
+    return gone.into_iter().map(|x| x.dir).collect();
+}
@@ -0,0 +1,678 @@
+// The .fswap file is a tab separated list of records, one per line:
+//
+//   source  NAME  PATH      a source directory, a .tar, .tar.gz or .zip archive, or a .diff or
+//                           .patch file. PATH is absolute or relative to the fswap directory
+//   swap    FILE  NAME      FILE is currently swapped in from source NAME
+//
+// source records can be followed by extra KEY=VALUE fields:
+//
+//   rev=REV                 files are read from git revision REV of the repository PATH is in,
+//                           instead of from PATH itself
+//   template=1              PATH can use ~ and $VARS, which are expanded when the source is used
+//
+// swap records can be followed by extra KEY=VALUE fields:
+//
+//   toggled=1               the original is in place and the swapped in file is saved as FILE.fswap
+//   hash=HEX                sha256 of the source file when it was swapped in
+//   content=HEX             sha256 of what was swapped in, if it isn't the whole source file
+//   under=NAME:HEX,...      FILE was swapped on top of these sources (and their hashes), bottom
+//                           first. The covered versions are kept in the store as layers/FILE.1,
+//                           layers/FILE.2, ...
+//
+// Every change to a file is appended to the history file in the store, one tab separated line
+// each: TIME USER COMMAND FILE BEFORE AFTER BATCH. BEFORE and AFTER are hashes of the file, or '-'
+// if it didn't exist. Changes made by the same run of fswap share a BATCH.
+//
+// Contents that have to be kept around, for undo and checkpoints, are stored once each as
+// objects/HEX, named after their hash.
+//
+// Older .fswap files only contain the path to a single source directory, these are read as a
+// source named 'default'.
+
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use std::process::exit;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{FSWAP_EXT, FSWAP_STORE, archive, diff, git, hash_bytes};
+
+pub const DEFAULT_SOURCE: &str = "default";
+
+pub struct Source {
+    pub name: String,
+    pub path: String,
+    pub rev: Option<String>,
+    pub template: bool,
+}
+
+pub struct Layer {
+    pub source: String,
+    pub hash: Option<String>,
+}
+
+pub struct Swap {
+    pub file: String,
+    pub source: String,
+    pub toggled: bool,
+    pub hash: Option<String>,
+    pub content: Option<String>,
+    pub under: Vec<Layer>,
+}
+
+pub struct Session {
+    pub dir: PathBuf,
+    pub sources: Vec<Source>,
+    pub swaps: Vec<Swap>,
+    pub batch: String,
+}
+
+pub struct Entry {
+    pub time: u64,
+    pub user: String,
+    pub command: String,
+    pub file: String,
+    pub before: Option<String>,
+    pub after: Option<String>,
+    pub batch: String,
+}
+
+impl Source {
+    pub fn is_archive(&self) -> bool {
+        self.rev.is_none() && archive::is_archive(Path::new(&self.path))
+    }
+
+    pub fn is_patch(&self) -> bool {
+        self.rev.is_none() && diff::is_patch(Path::new(&self.path))
+    }
+
+    // Only plain directories can be written to, or watched
+    pub fn is_dir(&self) -> bool {
+        self.rev.is_none() && !self.is_archive() && !self.is_patch()
+    }
+}
+
+impl Swap {
+    // Hash of the file right after it was swapped in, to tell if it was edited since
+    pub fn swapped_hash(&self) -> Option<&String> {
+        self.content.as_ref().or(self.hash.as_ref())
+    }
+
+    // Covers the current version with a new top layer
+    pub fn push_layer(&mut self, source: &str, hash: &str) {
+        self.under.push(Layer {
+            source: self.source.clone(),
+            hash: self.hash.clone(),
+        });
+        self.source = source.to_string();
+        self.hash = Some(hash.to_string());
+        self.content = None;
+    }
+
+    // Reads the fields of a swap record, returns the field it didn't understand on failure
+    pub fn parse(fields: &[&str]) -> Result<Self, String> {
+        let (file, source, extra) = match fields {
+            ["swap", file, source, extra @ ..] => (file, source, extra),
+            _ => return Err(fields.join("\t")),
+        };
+
+        let mut swap = Self {
+            file: file.to_string(),
+            source: source.to_string(),
+            toggled: false,
+            hash: None,
+            content: None,
+            under: vec![],
+        };
+
+        for field in extra {
+            match field.split_once('=') {
+                Some(("toggled", value)) => swap.toggled = value == "1",
+                Some(("hash", value)) => swap.hash = Some(value.to_string()),
+                Some(("content", value)) => swap.content = Some(value.to_string()),
+                Some(("under", value)) => {
+                    swap.under = value
+                        .split(',')
+                        .map(|x| match x.split_once(':') {
+                            Some((source, hash)) => Layer {
+                                source: source.to_string(),
+                                hash: Some(hash.to_string()),
+                            },
+                            None => Layer {
+                                source: x.to_string(),
+                                hash: None,
+                            },
+                        })
+                        .collect()
+                }
+                _ => return Err(field.to_string()),
+            }
+        }
+
+        return Ok(swap);
+    }
+
+    // The record as a line of the .fswap file, without the newline
+    pub fn serialize(&self) -> String {
+        let mut out = format!("swap\t{}\t{}", self.file, self.source);
+        if self.toggled {
+            out.push_str("\ttoggled=1");
+        }
+        if let Some(hash) = &self.hash {
+            out.push_str(&format!("\thash={hash}"));
+        }
+        if let Some(content) = &self.content {
+            out.push_str(&format!("\tcontent={content}"));
+        }
+        if !self.under.is_empty() {
+            let under: Vec<String> = self
+                .under
+                .iter()
+                .map(|x| match &x.hash {
+                    Some(hash) => format!("{}:{hash}", x.source),
+                    None => x.source.clone(),
+                })
+                .collect();
+            out.push_str(&format!("\tunder={}", under.join(",")));
+        }
+        return out;
+    }
+
+    pub fn pop_layer(&mut self) {
+        let layer = self.under.pop().expect("only called on swaps with layers");
+        self.source = layer.source;
+        self.hash = layer.hash;
+        self.content = None;
+    }
+}
+
+impl Session {
+    pub fn new(dir: &Path) -> Self {
+        let batch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |x| x.as_nanos());
+
+        Self {
+            dir: dir.to_path_buf(),
+            sources: vec![],
+            swaps: vec![],
+            batch: format!("{batch:x}"),
+        }
+    }
+
+    pub fn open(dir: &Path) -> Self {
+        return Self::read(dir).unwrap_or_else(|err| {
+            eprintln!("ERROR: {err}");
+            exit(1);
+        });
+    }
+
+    // Like open, but returns what's wrong with the .fswap file instead of exiting
+    pub fn read(dir: &Path) -> Result<Self, String> {
+        let path = dir.join(FSWAP_EXT);
+        let contents = match fs::read_to_string(&path) {
+            Ok(x) => x,
+            Err(err) => {
+                return Err(format!(
+                    "Couldn't open '{file}': {err}",
+                    file = path.display()
+                ));
+            }
+        };
+
+        let mut session = Self::new(dir);
+
+        if !contents.is_empty() && !contents.contains('\t') && !contents.contains('\n') {
+            session.sources.push(Source {
+                name: String::from(DEFAULT_SOURCE),
+                path: contents,
+                rev: None,
+                template: false,
+            });
+            return Ok(session);
+        }
+
+        for (n, line) in contents.lines().enumerate() {
+            if line.is_empty() {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split('\t').collect();
+            match fields.as_slice() {
+                ["source", name, dir, extra @ ..] => {
+                    let mut source = Source {
+                        name: name.to_string(),
+                        path: dir.to_string(),
+                        rev: None,
+                        template: false,
+                    };
+
+                    for field in extra {
+                        match field.split_once('=') {
+                            Some(("rev", value)) => source.rev = Some(value.to_string()),
+                            Some(("template", value)) => source.template = value == "1",
+                            _ => {
+                                return Err(format!(
+                                    "'{file}' line {line}: unknown field '{field}'.",
+                                    file = path.display(),
+                                    line = n + 1
+                                ));
+                            }
+                        }
+                    }
+
+                    session.sources.push(source);
+                }
+                ["swap", ..] => match Swap::parse(&fields) {
+                    Ok(swap) => session.swaps.push(swap),
+                    Err(field) => {
+                        return Err(format!(
+                            "'{file}' line {line}: unknown field '{field}'.",
+                            file = path.display(),
+                            line = n + 1
+                        ));
+                    }
+                },
+                _ => {
+                    return Err(format!(
+                        "'{file}' line {line}: malformed record.",
+                        file = path.display(),
+                        line = n + 1
+                    ));
+                }
+            }
+        }
+
+        return Ok(session);
+    }
+
+    pub fn save(&self) {
+        let path = self.dir.join(FSWAP_EXT);
+        if let Err(err) = fs::write(&path, self.serialize()) {
+            eprintln!(
+                "ERROR: Couldn't write to '{file}': {err}",
+                file = path.display()
+            );
+            exit(1);
+        }
+    }
+
+    pub fn serialize(&self) -> String {
+        let mut out = String::new();
+        for source in &self.sources {
+            out.push_str(&format!("source\t{}\t{}", source.name, source.path));
+            if let Some(rev) = &source.rev {
+                out.push_str(&format!("\trev={rev}"));
+            }
+            if source.template {
+                out.push_str("\ttemplate=1");
+            }
+            out.push('\n');
+        }
+        for swap in &self.swaps {
+            out.push_str(&swap.serialize());
+            out.push('\n');
+        }
+        return out;
+    }
+
+    pub fn source(&self, name: &str) -> Option<&Source> {
+        self.sources.iter().find(|x| x.name == name)
+    }
+
+    // Without a name, use 'default' if it exists, or the only source if there is just one
+    pub fn pick_source(&self, name: Option<&str>) -> &Source {
+        let source = match name {
+            Some(name) => self.source(name),
+            None => match self.source(DEFAULT_SOURCE) {
+                Some(x) => Some(x),
+                None if self.sources.len() == 1 => self.sources.first(),
+                None if self.sources.is_empty() => {
+                    eprintln!("ERROR: Session has no sources, add one with 'source add'.");
+                    exit(1);
+                }
+                None => {
+                    eprintln!("ERROR: Session has more than one source, pick one with '--from'.");
+                    exit(1);
+                }
+            },
+        };
+
+        return source.unwrap_or_else(|| {
+            eprintln!(
+                "ERROR: Unknown source '{name}'.",
+                name = name.unwrap_or(DEFAULT_SOURCE)
+            );
+            exit(1);
+        });
+    }
+
+    pub fn source_dir(&self, source: &Source) -> PathBuf {
+        if source.template {
+            return self.dir.join(expand(&source.path));
+        }
+        self.dir.join(&source.path)
+    }
+
+    // Where file can be read from source. Files of git revisions, archives and patches are written
+    // out to the store first, and are missing there if they don't exist in the source
+    pub fn source_file(&self, source: &Source, file: &str) -> PathBuf {
+        let contents = match &source.rev {
+            Some(rev) => git::show(&self.source_dir(source), rev, &normalize(file)),
+            None if source.is_archive() => {
+                archive::read(&self.source_dir(source), &normalize(file))
+            }
+            None if source.is_patch() => self.patched(source, &normalize(file)),
+            None => return self.source_dir(source).join(file),
+        };
+
+        let path = self
+            .dir
+            .join(FSWAP_STORE)
+            .join("extracted")
+            .join(&source.name)
+            .join(normalize(file));
+        let result = match contents {
+            Some(contents) => path
+                .parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .and_then(|_| fs::write(&path, contents)),
+            None if path.exists() => fs::remove_file(&path),
+            None => Ok(()),
+        };
+        if let Err(err) = result {
+            eprintln!(
+                "ERROR: Couldn't write to '{file}': {err}",
+                file = path.display()
+            );
+            exit(1);
+        }
+
+        return path;
+    }
+
+    // file with the patch of source applied. Patches always apply to the original, which is
+    // file.fswap once file is swapped. None if the patch doesn't touch file
+    fn patched(&self, source: &Source, file: &str) -> Option<Vec<u8>> {
+        let path = self.source_dir(source);
+        let text = fs::read_to_string(&path).unwrap_or_else(|err| {
+            eprintln!(
+                "ERROR: Couldn't read '{file}': {err}",
+                file = path.display()
+            );
+            exit(1);
+        });
+        let patches = diff::parse_patch(&text).unwrap_or_else(|line| {
+            eprintln!(
+                "ERROR: '{file}' line {line}: malformed hunk.",
+                file = path.display()
+            );
+            exit(1);
+        });
+        let patch = patches
+            .iter()
+            .find(|x| x.new_path.as_deref().map(normalize) == Some(file.to_string()))?;
+
+        let original = match self.swap(file) {
+            Some(swap) if !swap.toggled => self.dir.join(format!("{file}{FSWAP_EXT}")),
+            _ => self.dir.join(file),
+        };
+        let base = match patch.old_path {
+            Some(_) => fs::read_to_string(&original).unwrap_or_else(|err| {
+                eprintln!(
+                    "ERROR: Couldn't read '{file}' as text: {err}",
+                    file = original.display()
+                );
+                exit(1);
+            }),
+            None => String::new(),
+        };
+
+        let patched = diff::apply_patch(&base, &patch.hunks).unwrap_or_else(|n| {
+            eprintln!(
+                "ERROR: Hunk {n} of '{file}' in '{patch}' doesn't apply to '{original}'.",
+                n = n + 1,
+                patch = path.display(),
+                original = original.display()
+            );
+            exit(1);
+        });
+        return Some(patched.into_bytes());
+    }
+
+    pub fn swap(&self, file: &str) -> Option<&Swap> {
+        self.swaps.iter().find(|x| x.file == file)
+    }
+
+    pub fn swap_mut(&mut self, file: &str) -> Option<&mut Swap> {
+        self.swaps.iter_mut().find(|x| x.file == file)
+    }
+
+    pub fn set_swap(&mut self, file: &str, source: &str, hash: &str) {
+        match self.swap_mut(file) {
+            Some(swap) => {
+                swap.source = source.to_string();
+                swap.toggled = false;
+                swap.hash = Some(hash.to_string());
+                swap.content = None;
+            }
+            None => self.swaps.push(Swap {
+                file: file.to_string(),
+                source: source.to_string(),
+                toggled: false,
+                hash: Some(hash.to_string()),
+                content: None,
+                under: vec![],
+            }),
+        }
+    }
+
+    pub fn log(&self, command: &str, file: &str, before: Option<&str>, after: Option<&str>) {
+        let path = self.dir.join(FSWAP_STORE).join("history");
+        if let Err(err) = fs::create_dir_all(self.dir.join(FSWAP_STORE)) {
+            eprintln!(
+                "ERROR: Couldn't create '{dir}': {err}",
+                dir = self.dir.join(FSWAP_STORE).display()
+            );
+            exit(1);
+        }
+
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |x| x.as_secs());
+        let user = env::var("USER")
+            .or(env::var("LOGNAME"))
+            .unwrap_or(String::from("-"));
+        let line = format!(
+            "{time}\t{user}\t{command}\t{file}\t{before}\t{after}\t{batch}\n",
+            before = before.unwrap_or("-"),
+            after = after.unwrap_or("-"),
+            batch = self.batch
+        );
+
+        let result = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .and_then(|mut x| x.write_all(line.as_bytes()));
+        if let Err(err) = result {
+            eprintln!(
+                "ERROR: Couldn't write to '{file}': {err}",
+                file = path.display()
+            );
+            exit(1);
+        }
+    }
+
+    // Every entry of the history file, oldest first
+    pub fn history(&self) -> Vec<Entry> {
+        let path = self.dir.join(FSWAP_STORE).join("history");
+        if !path.exists() {
+            return vec![];
+        }
+
+        let contents = fs::read_to_string(&path).unwrap_or_else(|err| {
+            eprintln!(
+                "ERROR: Couldn't read '{file}': {err}",
+                file = path.display()
+            );
+            exit(1);
+        });
+
+        let hash = |x: &str| (x != "-").then(|| x.to_string());
+        let mut entries: Vec<Entry> = vec![];
+        for (n, line) in contents.lines().enumerate() {
+            let fields: Vec<&str> = line.split('\t').collect();
+            let (time, user, command, file, before, after, batch) = match fields.as_slice() {
+                [time, user, command, file, before, after, batch] => {
+                    (time, user, command, file, before, after, batch.to_string())
+                }
+                // Lines from before batches were recorded are a batch of their own
+                [time, user, command, file, before, after] => {
+                    (time, user, command, file, before, after, format!("line{n}"))
+                }
+                _ => {
+                    eprintln!(
+                        "ERROR: '{file}' line {line}: malformed entry.",
+                        file = path.display(),
+                        line = n + 1
+                    );
+                    exit(1);
+                }
+            };
+
+            entries.push(Entry {
+                time: time.parse().unwrap_or(0),
+                user: user.to_string(),
+                command: command.to_string(),
+                file: file.to_string(),
+                before: hash(before),
+                after: hash(after),
+                batch,
+            });
+        }
+
+        return entries;
+    }
+
+    // Where the version of file covered by layer n (1 is the lowest) is kept
+    pub fn layer_path(&self, file: &str, n: usize) -> PathBuf {
+        let mut path = self.dir.join(FSWAP_STORE);
+        path.push("layers");
+        path.push(format!("{file}.{n}"));
+        return path;
+    }
+
+    // Copies path into the store, where it's kept once under its hash. Returns the hash, or None
+    // if path doesn't exist
+    pub fn store_object(&self, path: &Path) -> Option<String> {
+        if !path.exists() {
+            return None;
+        }
+
+        let contents = fs::read(path).unwrap_or_else(|err| {
+            eprintln!(
+                "ERROR: Couldn't read '{file}': {err}",
+                file = path.display()
+            );
+            exit(1);
+        });
+        let hash = hash_bytes(&contents);
+
+        let dir = self.dir.join(FSWAP_STORE).join("objects");
+        let object = dir.join(&hash);
+        if !object.exists() {
+            let result = fs::create_dir_all(&dir).and_then(|_| fs::write(&object, &contents));
+            if let Err(err) = result {
+                eprintln!(
+                    "ERROR: Couldn't write to '{file}': {err}",
+                    file = object.display()
+                );
+                exit(1);
+            }
+        }
+        return Some(hash);
+    }
+
+    pub fn object(&self, hash: &str) -> Vec<u8> {
+        let object = self.dir.join(FSWAP_STORE).join("objects").join(hash);
+        return fs::read(&object).unwrap_or_else(|err| {
+            eprintln!(
+                "ERROR: Couldn't read '{file}': {err}",
+                file = object.display()
+            );
+            exit(1);
+        });
+    }
+
+    pub fn remove_swap(&mut self, file: &str) {
+        self.swaps.retain(|x| x.file != file);
+    }
+}
+
+// Files are recorded relative to the fswap directory without any leading './', so 'a/b' and
+// './a/b' refer to the same record
+pub fn normalize(file: &str) -> String {
+    let path: PathBuf = Path::new(file)
+        .components()
+        .filter(|x| *x != Component::CurDir)
+        .collect();
+    return path.display().to_string();
+}
+
+// Expands a leading '~' and $VAR or ${VAR} anywhere in path, from the environment
+pub fn expand(path: &str) -> String {
+    let var = |name: &str| -> String {
+        env::var(name).unwrap_or_else(|_| {
+            eprintln!("ERROR: '{path}' uses ${name}, which isn't set.");
+            exit(1);
+        })
+    };
+
+    let mut out = String::new();
+    let mut rest = path;
+    if rest == "~" || rest.starts_with("~/") {
+        out.push_str(&var("HOME"));
+        rest = &rest[1..];
+    }
+
+    while let Some(start) = rest.find('$') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+
+        let (name, len) = match rest.strip_prefix('{') {
+            Some(x) => match x.find('}') {
+                Some(end) => (&x[..end], end + 2),
+                None => {
+                    eprintln!("ERROR: '{path}' has an unclosed '${{'.");
+                    exit(1);
+                }
+            },
+            None => {
+                let end = rest
+                    .find(|c: char| !c.is_alphanumeric() && c != '_')
+                    .unwrap_or(rest.len());
+                (&rest[..end], end)
+            }
+        };
+
+        if name.is_empty() {
+            out.push('$');
+        } else {
+            out.push_str(&var(name));
+        }
+        rest = &rest[len..];
+    }
+
+    out.push_str(rest);
+    return out;
+}
+
+// Names end up in a tab separated file, so keep them simple
+pub fn valid_source_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || "-_.".contains(c))
+}
@@ -0,0 +1,104 @@
+// Moves files to the home trash of the freedesktop.org trash spec, $XDG_DATA_HOME/Trash
+// (~/.local/share/Trash by default), so desktop file managers can restore them. Each trashed file
+// is moved to Trash/files/NAME, next to a Trash/info/NAME.trashinfo record of where it came from
+// and when it was trashed.
+
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::{copy_tree, format_time};
+
+fn trash_dir() -> io::Result<PathBuf> {
+    let data = match env::var_os("XDG_DATA_HOME").filter(|x| !x.is_empty()) {
+        Some(x) => PathBuf::from(x),
+        None => match env::var_os("HOME") {
+            Some(home) => PathBuf::from(home).join(".local/share"),
+            None => return Err(io::Error::other("neither $XDG_DATA_HOME nor $HOME is set")),
+        },
+    };
+    return Ok(data.join("Trash"));
+}
+
+// Paths in .trashinfo files are URL escaped, except for '/'
+fn escape(path: &Path) -> String {
+    let mut out = String::new();
+    for byte in path.as_os_str().as_encoded_bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                out.push(*byte as char)
+            }
+            _ => out.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    return out;
+}
+
+// Moves the file or directory at path to the trash
+pub fn trash(path: &Path) -> io::Result<()> {
+    // Only the parent is resolved, a symlink is trashed itself and not what it points to
+    let file_name = path.file_name().ok_or(io::Error::other("can't trash it"))?;
+    let parent = match path.parent() {
+        Some(x) if !x.as_os_str().is_empty() => x,
+        _ => Path::new("."),
+    };
+    let path = fs::canonicalize(parent)?.join(file_name);
+    let name = file_name.to_string_lossy().to_string();
+
+    let dir = trash_dir()?;
+    fs::create_dir_all(dir.join("files"))?;
+    fs::create_dir_all(dir.join("info"))?;
+
+    // Creating the info file claims the name, files with the same name get a number added
+    let mut n = 1;
+    let (trashed, info, mut info_file) = loop {
+        let trashed = if n == 1 {
+            name.clone()
+        } else {
+            format!("{name}.{n}")
+        };
+        let info = dir.join("info").join(format!("{trashed}.trashinfo"));
+        n += 1;
+
+        if dir.join("files").join(&trashed).exists() {
+            continue;
+        }
+        match OpenOptions::new().write(true).create_new(true).open(&info) {
+            Ok(file) => break (dir.join("files").join(trashed), info, file),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        }
+    };
+
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |x| x.as_secs());
+    let result = write!(
+        info_file,
+        "[Trash Info]\nPath={path}\nDeletionDate={date}\n",
+        path = escape(&path),
+        date = format_time(now).replace(' ', "T")
+    )
+    .and_then(|_| move_path(&path, &trashed));
+
+    if result.is_err() {
+        let _ = fs::remove_file(&info);
+    }
+    return result;
+}
+
+// The trash may be on another filesystem, then path is copied over and removed
+fn move_path(path: &Path, dest: &Path) -> io::Result<()> {
+    match fs::rename(path, dest) {
+        Err(err) if err.kind() == io::ErrorKind::CrossesDevices => {
+            if path.is_dir() {
+                copy_tree(path, dest);
+                return fs::remove_dir_all(path);
+            }
+            fs::copy(path, dest)?;
+            return fs::remove_file(path);
+        }
+        result => return result,
+    }
+}
@@ -0,0 +1,303 @@
+// Undo and redo. Before a run of fswap first changes a file, everything it could touch is recorded
+// in the store under undo/BATCH/before: the file, FILE.fswap, its layers and its swap record.
+// Undoing records the same things again as undo/BATCH/after, then puts the before state back.
+//
+// States are tab separated lines:
+//
+//   file  PATH  HEX         PATH held the object HEX, or '-' if it didn't exist
+//   swap  FILE  ...         the swap record of FILE, as in the .fswap file
+//   none  FILE              FILE had no swap record
+//
+// The contents of the files are kept in the store's objects. undo/stack lists the batches
+// that can be undone, oldest first, and undo/redo the ones that were undone, most recent last.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+
+use crate::session::{Session, Swap};
+use crate::{FSWAP_EXT, FSWAP_STORE, append_to_pathbuf, hash_file};
+
+fn undo_dir(session: &Session) -> PathBuf {
+    session.dir.join(FSWAP_STORE).join("undo")
+}
+
+fn read_lines(path: &Path) -> Vec<String> {
+    if !path.exists() {
+        return vec![];
+    }
+
+    let contents = fs::read_to_string(path).unwrap_or_else(|err| {
+        eprintln!(
+            "ERROR: Couldn't read '{file}': {err}",
+            file = path.display()
+        );
+        exit(1);
+    });
+    return contents.lines().map(|x| x.to_string()).collect();
+}
+
+fn write_lines(path: &Path, lines: &[String]) {
+    let mut contents = lines.join("\n");
+    if !contents.is_empty() {
+        contents.push('\n');
+    }
+
+    if let Some(dir) = path.parent()
+        && let Err(err) = fs::create_dir_all(dir)
+    {
+        eprintln!("ERROR: Couldn't create '{dir}': {err}", dir = dir.display());
+        exit(1);
+    }
+    if let Err(err) = fs::write(path, contents) {
+        eprintln!(
+            "ERROR: Couldn't write to '{file}': {err}",
+            file = path.display()
+        );
+        exit(1);
+    }
+}
+
+// Copies path into the objects, returns its hash or '-' if it doesn't exist
+fn store(session: &Session, path: &str) -> String {
+    return session
+        .store_object(&session.dir.join(path))
+        .unwrap_or(String::from("-"));
+}
+
+fn write_file(path: &Path, contents: &[u8]) {
+    if let Some(dir) = path.parent()
+        && let Err(err) = fs::create_dir_all(dir)
+    {
+        eprintln!("ERROR: Couldn't create '{dir}': {err}", dir = dir.display());
+        exit(1);
+    }
+    if let Err(err) = fs::write(path, contents) {
+        eprintln!(
+            "ERROR: Couldn't write to '{file}': {err}",
+            file = path.display()
+        );
+        exit(1);
+    }
+}
+
+// The layer files of file that currently exist, as paths relative to the session
+fn layer_paths(session: &Session, file: &str) -> Vec<String> {
+    let mut paths: Vec<String> = vec![];
+    let mut n = 1;
+    while session.layer_path(file, n).exists() {
+        let path = session.layer_path(file, n);
+        let path = path.strip_prefix(&session.dir).unwrap_or(&path);
+        paths.push(path.display().to_string());
+        n += 1;
+    }
+    return paths;
+}
+
+// What file and everything kept for it look like right now
+fn capture(session: &Session, file: &str) -> Vec<String> {
+    let mut lines: Vec<String> = vec![];
+
+    let swapped = append_to_pathbuf(Path::new(file), FSWAP_EXT);
+    let mut paths = vec![file.to_string(), swapped.display().to_string()];
+    paths.append(&mut layer_paths(session, file));
+    for path in paths {
+        lines.push(format!(
+            "file\t{path}\t{hash}",
+            hash = store(session, &path)
+        ));
+    }
+
+    lines.push(match session.swap(file) {
+        Some(swap) => swap.serialize(),
+        None => format!("none\t{file}"),
+    });
+    return lines;
+}
+
+// Adds the batch to the undo stack the first time it changes something. Anything undone before
+// can't be redone anymore after that
+fn start_batch(session: &Session) -> PathBuf {
+    let dir = undo_dir(session);
+    let batch_dir = dir.join(&session.batch);
+    if batch_dir.exists() {
+        return batch_dir;
+    }
+
+    for batch in read_lines(&dir.join("redo")) {
+        let _ = fs::remove_dir_all(dir.join(batch));
+    }
+    write_lines(&dir.join("redo"), &[]);
+
+    let mut stack = read_lines(&dir.join("stack"));
+    stack.push(session.batch.clone());
+    write_lines(&dir.join("stack"), &stack);
+
+    if let Err(err) = fs::create_dir_all(&batch_dir) {
+        eprintln!(
+            "ERROR: Couldn't create '{dir}': {err}",
+            dir = batch_dir.display()
+        );
+        exit(1);
+    }
+    return batch_dir;
+}
+
+// Call before changing file, only the state before the first change of a run is kept
+pub fn record(session: &Session, file: &str) {
+    let path = start_batch(session).join("before");
+    let mut lines = read_lines(&path);
+
+    let recorded = lines.iter().any(|x| {
+        let fields: Vec<&str> = x.split('\t').collect();
+        matches!(fields.as_slice(), ["swap" | "none", name, ..] if *name == file)
+    });
+    if recorded {
+        return;
+    }
+
+    lines.append(&mut capture(session, file));
+    write_lines(&path, &lines);
+}
+
+// Same for a single file outside the session's bookkeeping, like a file in a source
+pub fn record_path(session: &Session, path: &Path) {
+    let path = path.strip_prefix(&session.dir).unwrap_or(path);
+    let path = path.display().to_string();
+    let state = start_batch(session).join("before");
+    let mut lines = read_lines(&state);
+
+    if has_file(&lines, &path) {
+        return;
+    }
+
+    lines.push(format!(
+        "file\t{path}\t{hash}",
+        hash = store(session, &path)
+    ));
+    write_lines(&state, &lines);
+}
+
+fn has_file(lines: &[String], path: &str) -> bool {
+    lines.iter().any(|x| {
+        let fields: Vec<&str> = x.split('\t').collect();
+        matches!(fields.as_slice(), ["file", name, _] if *name == path)
+    })
+}
+
+// Puts back the files and swap records of a recorded state. Returns the files it covers, with
+// their hashes from before they were put back
+fn restore(session: &mut Session, lines: &[String]) -> Vec<(String, Option<String>)> {
+    let mut files: Vec<(String, Option<String>)> = vec![];
+    let mut kept: Vec<String> = vec![];
+
+    for line in lines {
+        if let ["swap" | "none", file, ..] = line.split('\t').collect::<Vec<&str>>().as_slice() {
+            let path = session.dir.join(file);
+            files.push((file.to_string(), path.exists().then(|| hash_file(&path))));
+        }
+    }
+
+    for line in lines {
+        let fields: Vec<&str> = line.split('\t').collect();
+        match fields.as_slice() {
+            ["file", path, "-"] => {
+                let path = session.dir.join(path);
+                if path.exists()
+                    && let Err(err) = fs::remove_file(&path)
+                {
+                    eprintln!(
+                        "ERROR: couldn't remove '{file}': {err}",
+                        file = path.display()
+                    );
+                    exit(1);
+                }
+            }
+            ["file", path, hash] => {
+                write_file(&session.dir.join(path), &session.object(hash));
+                kept.push(path.to_string());
+            }
+            ["swap", file, ..] => {
+                let swap = Swap::parse(&fields).unwrap_or_else(|field| {
+                    eprintln!("ERROR: Unknown field '{field}' in the undo state of '{file}'.");
+                    exit(1);
+                });
+                match session.swap_mut(file) {
+                    Some(x) => *x = swap,
+                    None => session.swaps.push(swap),
+                }
+            }
+            ["none", file] => session.remove_swap(file),
+            _ => {
+                eprintln!("ERROR: Malformed undo state '{line}'.");
+                exit(1);
+            }
+        }
+    }
+
+    // Layers added after the state was recorded
+    for (file, _) in &files {
+        for path in layer_paths(session, file) {
+            if !kept.contains(&path)
+                && let Err(err) = fs::remove_file(session.dir.join(&path))
+            {
+                eprintln!("ERROR: couldn't remove '{path}': {err}");
+                exit(1);
+            }
+        }
+    }
+
+    session.save();
+    return files;
+}
+
+// Puts the most recent batch back the way it was before, returns the files it touched. None if
+// there is nothing to undo
+pub fn undo(session: &mut Session) -> Option<Vec<(String, Option<String>)>> {
+    let dir = undo_dir(session);
+    let mut stack = read_lines(&dir.join("stack"));
+    let batch = stack.pop()?;
+    let before = read_lines(&dir.join(&batch).join("before"));
+
+    // Everything the batch may have changed, as it is now
+    let mut after: Vec<String> = vec![];
+    for line in &before {
+        if let ["swap" | "none", file, ..] = line.split('\t').collect::<Vec<&str>>().as_slice() {
+            after.append(&mut capture(session, file));
+        }
+    }
+    for line in &before {
+        if let ["file", path, _] = line.split('\t').collect::<Vec<&str>>().as_slice()
+            && !has_file(&after, path)
+        {
+            after.push(format!("file\t{path}\t{hash}", hash = store(session, path)));
+        }
+    }
+    write_lines(&dir.join(&batch).join("after"), &after);
+
+    let files = restore(session, &before);
+
+    let mut redo = read_lines(&dir.join("redo"));
+    redo.push(batch);
+    write_lines(&dir.join("redo"), &redo);
+    write_lines(&dir.join("stack"), &stack);
+
+    return Some(files);
+}
+
+// Puts the most recently undone batch back the way it was after it ran
+pub fn redo(session: &mut Session) -> Option<Vec<(String, Option<String>)>> {
+    let dir = undo_dir(session);
+    let mut redo = read_lines(&dir.join("redo"));
+    let batch = redo.pop()?;
+    let after = read_lines(&dir.join(&batch).join("after"));
+
+    let files = restore(session, &after);
+
+    let mut stack = read_lines(&dir.join("stack"));
+    stack.push(batch);
+    write_lines(&dir.join("stack"), &stack);
+    write_lines(&dir.join("redo"), &redo);
+
+    return Some(files);
+}
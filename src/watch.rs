@@ -0,0 +1,169 @@
+// Minimal inotify wrapper, watches directory trees for files being written or moved into place
+
+use std::collections::HashMap;
+use std::ffi::{CString, OsStr};
+use std::fs;
+use std::io;
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_interrupt(_: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+// Ctrl-C sets a flag instead of killing the process, so the caller can clean up
+pub fn catch_interrupt() {
+    unsafe {
+        libc::signal(
+            libc::SIGINT,
+            on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        );
+    }
+}
+
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+pub struct Watcher {
+    fd: libc::c_int,
+    dirs: HashMap<libc::c_int, PathBuf>,
+}
+
+impl Watcher {
+    pub fn new() -> Self {
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+        if fd < 0 {
+            eprintln!(
+                "ERROR: Couldn't initialize inotify: {err}",
+                err = io::Error::last_os_error()
+            );
+            exit(1);
+        }
+
+        Self {
+            fd,
+            dirs: HashMap::new(),
+        }
+    }
+
+    // Watches dir and every directory below it
+    pub fn add_tree(&mut self, dir: &Path) {
+        let path = CString::new(dir.as_os_str().as_bytes()).unwrap_or_else(|_| {
+            eprintln!("ERROR: '{dir}' contains a nul byte.", dir = dir.display());
+            exit(1);
+        });
+
+        let mask = libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO | libc::IN_CREATE;
+        let wd = unsafe { libc::inotify_add_watch(self.fd, path.as_ptr(), mask) };
+        if wd < 0 {
+            eprintln!(
+                "ERROR: Couldn't watch '{dir}': {err}",
+                dir = dir.display(),
+                err = io::Error::last_os_error()
+            );
+            exit(1);
+        }
+        self.dirs.insert(wd, dir.to_path_buf());
+
+        let entries = fs::read_dir(dir).unwrap_or_else(|err| {
+            eprintln!(
+                "ERROR: Couldn't read dir '{dir}': {err}",
+                dir = dir.display()
+            );
+            exit(1);
+        });
+
+        for entry in entries.flatten() {
+            if entry.file_type().is_ok_and(|x| x.is_dir()) {
+                self.add_tree(&entry.path());
+            }
+        }
+    }
+
+    // Waits up to timeout_ms for changes, returns the files that were written. Returns early
+    // with nothing when interrupted
+    pub fn wait(&mut self, timeout_ms: libc::c_int) -> Vec<PathBuf> {
+        let mut changed: Vec<PathBuf> = vec![];
+
+        let mut pfd = libc::pollfd {
+            fd: self.fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+
+        let ready = unsafe { libc::poll(&mut pfd, 1, timeout_ms) };
+        if ready < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                return changed;
+            }
+            eprintln!("ERROR: Couldn't poll inotify: {err}");
+            exit(1);
+        }
+        if ready == 0 {
+            return changed;
+        }
+
+        // inotify_event is followed by a nul padded name, so the buffer has to be aligned for it
+        let mut buf = [0u64; 512];
+        let len = unsafe {
+            libc::read(
+                self.fd,
+                buf.as_mut_ptr() as *mut libc::c_void,
+                mem::size_of_val(&buf),
+            )
+        };
+        if len < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                return changed;
+            }
+            eprintln!("ERROR: Couldn't read inotify events: {err}");
+            exit(1);
+        }
+
+        let bytes = unsafe { std::slice::from_raw_parts(buf.as_ptr() as *const u8, len as usize) };
+        let header = mem::size_of::<libc::inotify_event>();
+        let mut offset = 0;
+        while offset + header <= bytes.len() {
+            let event = unsafe {
+                (bytes.as_ptr().add(offset) as *const libc::inotify_event).read_unaligned()
+            };
+            let name = &bytes[offset + header..offset + header + event.len as usize];
+            let name = OsStr::from_bytes(name.split(|x| *x == 0).next().unwrap_or(&[]));
+            offset += header + event.len as usize;
+
+            let dir = match self.dirs.get(&event.wd) {
+                Some(x) => x.clone(),
+                None => continue,
+            };
+            let path = dir.join(name);
+
+            if event.mask & libc::IN_ISDIR != 0 {
+                if event.mask & (libc::IN_CREATE | libc::IN_MOVED_TO) != 0 {
+                    self.add_tree(&path);
+                }
+            } else if event.mask & (libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO) != 0
+                && !changed.contains(&path)
+            {
+                changed.push(path);
+            }
+        }
+
+        return changed;
+    }
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}